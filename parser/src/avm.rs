@@ -1,12 +1,52 @@
+use crate::grammar::*;
 use crate::rewr::*;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::fmt;
+use std::rc::Rc;
+
+/// A view of an AVM value.
+#[derive(Clone, Copy, Debug)]
+pub enum AvmView<'a> {
+    /// An atomic value.
+    Atom(&'a str),
+    /// An AVM.
+    Avm(&'a Avm),
+    /// A reference to the coindexed AVM with the given tag.
+    Tag(usize),
+}
+
+/// An error returned when two AVMs don't unify.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct UnificationError;
+
+impl fmt::Display for UnificationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "AVMs don't unify")
+    }
+}
 
 /// An AVM value such as an atom or an AVM.
 trait AvmValue: fmt::Debug {
+    /// Returns a view of the value.
+    fn view(&self) -> AvmView<'_>;
     fn as_avm_mut(&mut self) -> Option<&mut Avm> {
         None
     }
+    /// Writes the value as JSON.
+    fn write_json(&self, json: &mut String);
+    /// Collects constraints on the value at the given path, recording the paths of coindexed values.
+    fn collect_constraints(
+        &self,
+        path: Term,
+        tagged: &mut HashMap<usize, Vec<Term>>,
+        constraints: &mut Vec<Constraint>,
+    );
+    /// Returns the lines of the value rendered as plain text.
+    fn text_lines(&self) -> Vec<String>;
+    /// Writes the value as `langsci-avm` markup.
+    fn write_latex(&self, latex: &mut String);
+    /// Writes the DOT nodes and edges of the value and returns the identifier of its node.
+    fn write_dot(&self, dot: &mut DotGraph) -> String;
 }
 
 // A string constant.
@@ -14,7 +54,34 @@ struct AvmString {
     value: String,
 }
 
-impl AvmValue for AvmString {}
+impl AvmValue for AvmString {
+    fn view(&self) -> AvmView<'_> {
+        AvmView::Atom(&self.value)
+    }
+    fn write_json(&self, json: &mut String) {
+        write_json_string(json, &self.value);
+    }
+    fn collect_constraints(
+        &self,
+        path: Term,
+        _tagged: &mut HashMap<usize, Vec<Term>>,
+        constraints: &mut Vec<Constraint>,
+    ) {
+        let atom = Term::new(&format!("@{}", self.value), &[]);
+        constraints.push(Constraint::Equal(path, atom));
+    }
+    fn text_lines(&self) -> Vec<String> {
+        vec![self.value.to_owned()]
+    }
+    fn write_latex(&self, latex: &mut String) {
+        *latex += &latex_escape(&self.value);
+    }
+    fn write_dot(&self, dot: &mut DotGraph) -> String {
+        let id = dot.new_id();
+        dot.out += &format!("  {} [label={:?}, shape=plaintext];\n", id, self.value);
+        id
+    }
+}
 
 impl AvmString {
     fn new(s: &str) -> Self {
@@ -30,56 +97,779 @@ impl fmt::Debug for AvmString {
     }
 }
 
+// A reference to a coindexed AVM.
+// It closes a cycle if the AVM it refers to contains it.
+struct AvmTag {
+    tag: usize,
+    cycle: bool,
+}
+
+impl AvmValue for AvmTag {
+    fn view(&self) -> AvmView<'_> {
+        AvmView::Tag(self.tag)
+    }
+    fn write_json(&self, json: &mut String) {
+        if self.cycle {
+            *json += &format!("{{\"#ref\":{},\"#cycle\":true}}", self.tag);
+        } else {
+            *json += &format!("{{\"#ref\":{}}}", self.tag);
+        }
+    }
+    fn collect_constraints(
+        &self,
+        path: Term,
+        tagged: &mut HashMap<usize, Vec<Term>>,
+        _constraints: &mut Vec<Constraint>,
+    ) {
+        tagged.entry(self.tag).or_default().push(path);
+    }
+    fn text_lines(&self) -> Vec<String> {
+        vec![format!("{:?}", self)]
+    }
+    fn write_latex(&self, latex: &mut String) {
+        *latex += &latex_tag(self.tag);
+    }
+    fn write_dot(&self, dot: &mut DotGraph) -> String {
+        dot.tag_id(self.tag)
+    }
+}
+
+impl fmt::Debug for AvmTag {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.cycle {
+            write!(f, "[{}]<cycle>", self.tag)
+        } else {
+            write!(f, "[{}]", self.tag)
+        }
+    }
+}
+
 /// An AVM.
+/// Structures shared by several paths are tagged, the first occurrence holding the structure
+/// and the other ones referring to it by its tag.
 pub struct Avm {
-    features: HashMap<String, Box<dyn AvmValue>>,
+    features: BTreeMap<String, Box<dyn AvmValue>>,
+    tag: Option<usize>,
+    var: Option<String>,
 }
 
 impl AvmValue for Avm {
+    fn view(&self) -> AvmView<'_> {
+        AvmView::Avm(self)
+    }
     fn as_avm_mut(&mut self) -> Option<&mut Avm> {
         Some(self)
     }
+    fn write_json(&self, json: &mut String) {
+        let mut fields = Vec::new();
+        if let Some(tag) = self.tag {
+            fields.push(format!("\"#tag\":{}", tag));
+        }
+        if let Some(var) = &self.var {
+            let mut s = "\"#var\":".to_owned();
+            write_json_string(&mut s, var);
+            fields.push(s);
+        }
+        for (attr, value) in self.sorted_features() {
+            let mut s = String::new();
+            write_json_string(&mut s, attr);
+            s += ":";
+            value.write_json(&mut s);
+            fields.push(s);
+        }
+        *json += &format!("{{{}}}", fields.join(","));
+    }
+    fn collect_constraints(
+        &self,
+        path: Term,
+        tagged: &mut HashMap<usize, Vec<Term>>,
+        constraints: &mut Vec<Constraint>,
+    ) {
+        if let Some(tag) = self.tag {
+            tagged.entry(tag).or_default().push(path.clone());
+        }
+        if self.features.is_empty() {
+            let var = Term::new(&format!("{}#{}", path.last(), constraints.len()), &[]);
+            constraints.push(Constraint::Equal(path.clone(), var));
+        }
+        for (attr, value) in self.sorted_features() {
+            let path = Term {
+                head: Rc::from(attr.as_str()),
+                arg: Some(Rc::new(path.clone())),
+            };
+            value.collect_constraints(path, tagged, constraints);
+        }
+    }
+    fn text_lines(&self) -> Vec<String> {
+        let features = self.sorted_features();
+        let width = features
+            .iter()
+            .map(|(attr, _)| attr.chars().count())
+            .max()
+            .unwrap_or(0);
+        let mut body = Vec::new();
+        for (attr, value) in features {
+            for (i, line) in value.text_lines().into_iter().enumerate() {
+                let attr = if i == 0 { attr.as_str() } else { "" };
+                body.push(format!("{:<width$}  {}", attr, line, width = width));
+            }
+        }
+        let mut prefix = String::new();
+        if let Some(tag) = self.tag {
+            prefix += &format!("[{}]", tag);
+        }
+        if let Some(var) = &self.var {
+            prefix += &format!("<{}>", var);
+        }
+        if body.is_empty() {
+            return vec![format!("{}[ ]", prefix)];
+        }
+        let indent = " ".repeat(prefix.chars().count());
+        let inner = body.iter().map(|line| line.chars().count()).max().unwrap();
+        body.iter()
+            .enumerate()
+            .map(|(i, line)| {
+                let prefix = if i == 0 { &prefix } else { &indent };
+                format!("{}[ {:<inner$} ]", prefix, line, inner = inner)
+            })
+            .collect()
+    }
+    fn write_latex(&self, latex: &mut String) {
+        if let Some(tag) = self.tag {
+            *latex += &latex_tag(tag);
+            *latex += " ";
+        }
+        let rows = self
+            .sorted_features()
+            .into_iter()
+            .map(|(attr, value)| {
+                let mut row = latex_escape(attr);
+                row += " & ";
+                value.write_latex(&mut row);
+                row
+            })
+            .collect::<Vec<_>>();
+        if rows.is_empty() {
+            *latex += "[ ]";
+        } else {
+            *latex += &format!("[ {} ]", rows.join(" \\\\ "));
+        }
+    }
+    fn write_dot(&self, dot: &mut DotGraph) -> String {
+        let id = match self.tag {
+            Some(tag) => dot.tag_id(tag),
+            None => dot.new_id(),
+        };
+        let mut label = String::new();
+        if let Some(tag) = self.tag {
+            label += &format!("[{}]", tag);
+        }
+        if let Some(var) = &self.var {
+            label += var;
+            dot.vars.insert(var.to_owned(), id.to_owned());
+        }
+        dot.out += &format!("  {} [label={:?}, shape=circle];\n", id, label);
+        for (attr, value) in self.sorted_features() {
+            let value_id = value.write_dot(dot);
+            dot.out += &format!("  {} -> {} [label={:?}];\n", id, value_id, attr);
+        }
+        id
+    }
 }
 
 impl Avm {
-    fn new() -> Self {
+    /// Creates an empty AVM.
+    pub fn new() -> Self {
         Self {
-            features: HashMap::new(),
+            features: BTreeMap::new(),
+            tag: None,
+            var: None,
         }
     }
-    /// Returns the AVM represented by the given rewriting system.
-    pub fn from_theory(rs: &RewritingSystem, logvar: &str) -> Self {
-        let logvar = &rs.norm(&Term::new(logvar, &[])).head;
-        let mut avm = Self {
-            features: HashMap::new(),
-        };
-        for rule in &rs.rules {
-            if rule.lhs.arg != None && rule.lhs.last() == logvar.as_ref() {
-                if rule.rhs.head.chars().next().unwrap() == '@' {
-                    let mut path = rule.lhs.as_vec();
-                    path.reverse();
-                    avm.set(&path[1..], Box::new(AvmString::new(&rule.rhs.head[1..])));
+    /// Returns the coindexation tag of the AVM (if it's shared).
+    pub fn tag(&self) -> Option<usize> {
+        self.tag
+    }
+    /// Sets the coindexation tag of the AVM.
+    pub fn set_tag(&mut self, tag: Option<usize>) {
+        self.tag = tag;
+    }
+    /// Returns the variable denoting the AVM (if extracted).
+    pub fn var(&self) -> Option<&str> {
+        self.var.as_deref()
+    }
+    /// Returns the features of the AVM in alphabetical order.
+    pub fn features(&self) -> impl Iterator<Item = (&str, AvmView<'_>)> {
+        self.sorted_features()
+            .into_iter()
+            .map(|(attr, value)| (attr.as_str(), value.view()))
+    }
+    /// Returns the value at the given path.
+    /// Coindexed references are resolved to the tagged AVMs they refer to.
+    pub fn get(&self, path: &[&str]) -> Option<AvmView<'_>> {
+        let mut view = AvmView::Avm(self);
+        for attr in path {
+            view = match view {
+                AvmView::Avm(avm) => avm.features.get(*attr)?.view(),
+                _ => return None,
+            };
+            if let AvmView::Tag(tag) = view {
+                view = AvmView::Avm(self.tagged(tag)?);
+            }
+        }
+        Some(view)
+    }
+    /// Sets an atomic value for the given path, creating intermediate AVMs as needed.
+    /// Atomic values found on the way are replaced with AVMs.
+    pub fn set_atom(&mut self, path: &[&str], value: &str) {
+        let path = self.canonical_path(path);
+        self.set(&path, Box::new(AvmString::new(value)));
+    }
+    /// Sets an AVM for the given path, creating intermediate AVMs as needed.
+    /// Atomic values found on the way are replaced with AVMs.
+    pub fn set_avm(&mut self, path: &[&str], value: Avm) {
+        let path = self.canonical_path(path);
+        self.set(&path, Box::new(value));
+    }
+    /// Sets a reference to the coindexed AVM with the given tag for the given path.
+    pub fn set_ref(&mut self, path: &[&str], tag: usize) {
+        let path = self.canonical_path(path);
+        self.set(
+            &path,
+            Box::new(AvmTag {
+                tag: tag,
+                cycle: false,
+            }),
+        );
+    }
+    /// Unifies the AVM with another one using a rewriting system.
+    /// Tags of the result are assigned anew.
+    pub fn unify(&self, other: &Avm) -> Result<Avm, UnificationError> {
+        // The operands are described with different variables so that their fresh variables don't clash.
+        let mut constraints = self.to_constraints("u");
+        constraints.extend(other.to_constraints("v"));
+        constraints.push(Constraint::Equal(Term::new("u", &[]), Term::new("v", &[])));
+        let mut rs = RewritingSystem::new();
+        for c in &constraints {
+            if !rs.add_rule(c.rule()) {
+                return Err(UnificationError);
+            }
+        }
+        Ok(Avm::from_theory(&rs, "u"))
+    }
+    /// Checks whether the AVM subsumes the given one, i.e. whether all its values and coindexations
+    /// are present in the other AVM.
+    /// As in unification, an empty AVM stands for any value and thus subsumes atoms too.
+    pub fn subsumes(&self, other: &Avm) -> bool {
+        for (path, view) in self.paths() {
+            let path = path.iter().map(|attr| attr.as_str()).collect::<Vec<_>>();
+            let other_view = other.get(&path);
+            match (view, other_view) {
+                (AvmView::Atom(a1), Some(AvmView::Atom(a2))) if a1 == a2 => {}
+                (AvmView::Avm(_), Some(AvmView::Avm(_))) => {}
+                (AvmView::Avm(avm), Some(AvmView::Atom(_))) if avm.features.is_empty() => {}
+                (AvmView::Tag(tag), Some(view2)) => {
+                    let tagged_path = self.tagged_path(tag).unwrap_or_default();
+                    let tagged_path = tagged_path
+                        .iter()
+                        .map(|attr| attr.as_str())
+                        .collect::<Vec<_>>();
+                    match (other.get(&tagged_path), view2) {
+                        (Some(AvmView::Avm(avm1)), AvmView::Avm(avm2))
+                            if std::ptr::eq(avm1, avm2) => {}
+                        // Coindexed values that became atoms are no longer shared but must be equal.
+                        (Some(AvmView::Atom(a1)), AvmView::Atom(a2)) if a1 == a2 => {}
+                        _ => return false,
+                    }
                 }
+                _ => return false,
             }
         }
-        avm
+        true
+    }
+    /// Returns all paths of the AVM and their values without following coindexed references.
+    fn paths(&self) -> Vec<(Vec<String>, AvmView<'_>)> {
+        let mut paths = Vec::new();
+        for (attr, value) in self.sorted_features() {
+            let view = value.view();
+            paths.push((vec![attr.to_owned()], view));
+            if let AvmView::Avm(avm) = view {
+                for (mut path, view) in avm.paths() {
+                    path.insert(0, attr.to_owned());
+                    paths.push((path, view));
+                }
+            }
+        }
+        paths
+    }
+    /// Returns the AVM with the given tag.
+    fn tagged(&self, tag: usize) -> Option<&Avm> {
+        if self.tag == Some(tag) {
+            return Some(self);
+        }
+        self.features
+            .values()
+            .filter_map(|value| match value.view() {
+                AvmView::Avm(avm) => avm.tagged(tag),
+                _ => None,
+            })
+            .next()
     }
-    /// Sets a value for the given path.
+    /// Returns the path of the AVM with the given tag.
+    fn tagged_path(&self, tag: usize) -> Option<Vec<String>> {
+        if self.tag == Some(tag) {
+            return Some(Vec::new());
+        }
+        for (attr, value) in self.sorted_features() {
+            if let AvmView::Avm(avm) = value.view() {
+                if let Some(mut path) = avm.tagged_path(tag) {
+                    path.insert(0, attr.to_owned());
+                    return Some(path);
+                }
+            }
+        }
+        None
+    }
+    /// Returns the path with prefixes leading to coindexed references replaced with the paths of the tagged AVMs.
+    fn canonical_path(&self, path: &[&str]) -> Vec<String> {
+        let mut canonical = Vec::new();
+        for attr in path {
+            canonical.push(attr.to_string());
+            let prefix = canonical
+                .iter()
+                .map(|attr| attr.as_str())
+                .collect::<Vec<_>>();
+            if let Some(AvmView::Tag(tag)) = self.get_unresolved(&prefix) {
+                if let Some(tagged_path) = self.tagged_path(tag) {
+                    canonical = tagged_path;
+                }
+            }
+        }
+        canonical
+    }
+    fn get_unresolved(&self, path: &[&str]) -> Option<AvmView<'_>> {
+        let mut view = AvmView::Avm(self);
+        for attr in path {
+            view = match view {
+                AvmView::Avm(avm) => avm.features.get(*attr)?.view(),
+                _ => return None,
+            };
+        }
+        Some(view)
+    }
+    /// Sets a value for the given path, replacing atomic values on the way with AVMs.
     fn set(&mut self, path: &[String], value: Box<dyn AvmValue>) {
         let attr = path.first().unwrap().to_owned();
         if path.len() == 1 {
             self.features.insert(attr, value);
         } else {
             let val = self.features.entry(attr).or_insert(Box::new(Avm::new()));
-            if let Some(avm) = val.as_avm_mut() {
-                avm.set(&path[1..], value);
+            if val.as_avm_mut().is_none() {
+                *val = Box::new(Avm::new());
+            }
+            val.as_avm_mut().unwrap().set(&path[1..], value);
+        }
+    }
+    /// Returns the AVM represented by the given rewriting system.
+    /// It is obtained by walking the equivalence classes (i.e. normal forms) reachable from the logical variable.
+    pub fn from_theory(rs: &RewritingSystem, logvar: &str) -> Self {
+        Self::extract(rs, logvar, false)
+    }
+    /// Returns the AVM represented by the given rewriting system
+    /// with each structure annotated with the variable denoting it (if any).
+    pub fn from_theory_with_vars(rs: &RewritingSystem, logvar: &str) -> Self {
+        Self::extract(rs, logvar, true)
+    }
+    fn extract(rs: &RewritingSystem, logvar: &str, with_vars: bool) -> Self {
+        let graph = rs.feature_graph();
+        let root = rs.norm(&Term::new(logvar, &[]));
+        let mut indegrees = HashMap::new();
+        indegrees.insert(root.clone(), 1);
+        for node in Self::reachable_nodes(&graph, &root) {
+            if let Some(features) = graph.get(&node) {
+                for value in features.values() {
+                    if !value.is_atom() {
+                        *indegrees.entry(value.clone()).or_insert(0) += 1;
+                    }
+                }
             }
         }
+        let mut extraction = AvmExtraction {
+            graph: &graph,
+            shared: indegrees
+                .into_iter()
+                .filter(|(_, n)| *n > 1)
+                .map(|(node, _)| node)
+                .collect(),
+            tags: HashMap::new(),
+            path: Vec::new(),
+            with_vars: with_vars,
+        };
+        extraction.avm(&root)
+    }
+    /// Returns the AVM as JSON with attributes in alphabetical order.
+    /// A shared structure carries its tag in the `#tag` field and is referred to as `{"#ref": tag}`
+    /// (with `"#cycle": true` added if the reference is inside the structure itself),
+    /// variables (if extracted) are stored in the `#var` field.
+    pub fn to_json(&self) -> String {
+        let mut json = String::new();
+        self.write_json(&mut json);
+        json
+    }
+    /// Returns the AVM drawn as a bracketed multi-line matrix with aligned attributes.
+    pub fn to_text(&self) -> String {
+        self.text_lines().join("\n")
+    }
+    /// Returns the AVM as markup for the `langsci-avm` LaTeX package.
+    /// Variables (if extracted) are not exported.
+    pub fn to_latex(&self) -> String {
+        let mut latex = String::new();
+        self.write_latex(&mut latex);
+        format!("\\avm{{{}}}", latex)
+    }
+    /// Returns the AVM as a graph in the DOT language, drawing each shared structure once.
+    pub fn to_dot(&self) -> String {
+        let mut dot = String::new();
+        self.write_dot_nodes("n", &mut dot);
+        format!("digraph avm {{\n{}}}\n", dot)
+    }
+    /// Writes the DOT nodes and edges of the AVM with identifiers starting with the given prefix.
+    /// Returns the map from variables (if extracted) to the identifiers of their nodes.
+    pub fn write_dot_nodes(&self, prefix: &str, dot: &mut String) -> HashMap<String, String> {
+        let mut graph = DotGraph {
+            out: String::new(),
+            prefix: prefix.to_owned(),
+            next_id: 0,
+            vars: HashMap::new(),
+        };
+        self.write_dot(&mut graph);
+        *dot += &graph.out;
+        graph.vars
+    }
+    /// Returns constraints describing the AVM as the f-structure of the given variable.
+    /// Atomic values and coindexations are expressed as equations, unvalued features are equated with fresh variables.
+    pub fn to_constraints(&self, var: &str) -> Vec<Constraint> {
+        let mut tagged = HashMap::new();
+        let mut constraints = Vec::new();
+        self.collect_constraints(Term::new(var, &[]), &mut tagged, &mut constraints);
+        let mut tagged = tagged.into_iter().collect::<Vec<_>>();
+        tagged.sort_by_key(|(tag, _)| *tag);
+        for (_, paths) in tagged {
+            for path in &paths[1..] {
+                constraints.push(Constraint::Equal(paths[0].clone(), path.clone()));
+            }
+        }
+        constraints
+    }
+    fn sorted_features(&self) -> Vec<(&String, &dyn AvmValue)> {
+        self.features
+            .iter()
+            .map(|(attr, value)| (attr, value.as_ref()))
+            .collect()
+    }
+    /// Returns the nodes reachable from the given node (including itself).
+    fn reachable_nodes(
+        graph: &BTreeMap<Term, BTreeMap<String, Term>>,
+        node: &Term,
+    ) -> HashSet<Term> {
+        let mut nodes = HashSet::new();
+        let mut stack = vec![node.clone()];
+        while let Some(node) = stack.pop() {
+            if let Some(features) = graph.get(&node) {
+                for value in features.values() {
+                    if !nodes.contains(value) {
+                        stack.push(value.clone());
+                    }
+                }
+            }
+            nodes.insert(node);
+        }
+        nodes
+    }
+}
+
+impl Default for Avm {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Writes a string as a JSON string literal.
+fn write_json_string(json: &mut String, s: &str) {
+    *json += "\"";
+    for c in s.chars() {
+        match c {
+            '"' => *json += "\\\"",
+            '\\' => *json += "\\\\",
+            '\n' => *json += "\\n",
+            '\r' => *json += "\\r",
+            '\t' => *json += "\\t",
+            c if (c as u32) < 0x20 => *json += &format!("\\u{:04x}", c as u32),
+            c => json.push(c),
+        }
+    }
+    *json += "\"";
+}
+
+/// Escapes characters with a special meaning in LaTeX.
+fn latex_escape(s: &str) -> String {
+    let mut latex = String::new();
+    for c in s.chars() {
+        match c {
+            '\\' => latex += "\\textbackslash{}",
+            '~' => latex += "\\textasciitilde{}",
+            '^' => latex += "\\textasciicircum{}",
+            '{' | '}' | '$' | '&' | '#' | '_' | '%' => {
+                latex.push('\\');
+                latex.push(c);
+            }
+            c => latex.push(c),
+        }
+    }
+    latex
+}
+
+/// Returns the `langsci-avm` markup for a coindexation tag.
+fn latex_tag(tag: usize) -> String {
+    if tag < 10 {
+        format!("\\{}", tag)
+    } else {
+        format!("\\tag{{{}}}", tag)
+    }
+}
+
+// A graph in the DOT language being written.
+struct DotGraph {
+    out: String,
+    prefix: String,
+    next_id: usize,
+    vars: HashMap<String, String>,
+}
+
+impl DotGraph {
+    fn new_id(&mut self) -> String {
+        self.next_id += 1;
+        format!("{}{}", self.prefix, self.next_id)
+    }
+    fn tag_id(&self, tag: usize) -> String {
+        format!("{}t{}", self.prefix, tag)
+    }
+}
+
+// The state of an extraction of an AVM from a feature graph.
+struct AvmExtraction<'a> {
+    graph: &'a BTreeMap<Term, BTreeMap<String, Term>>,
+    shared: HashSet<Term>,
+    tags: HashMap<Term, usize>,
+    // The nodes containing the one being extracted, to mark references closing a cycle.
+    path: Vec<Term>,
+    with_vars: bool,
+}
+
+impl AvmExtraction<'_> {
+    fn value(&mut self, node: &Term) -> Box<dyn AvmValue> {
+        if node.is_atom() {
+            Box::new(AvmString::new(&node.head[1..]))
+        } else if let Some(&tag) = self.tags.get(node) {
+            Box::new(AvmTag {
+                tag: tag,
+                cycle: self.path.contains(node),
+            })
+        } else {
+            Box::new(self.avm(node))
+        }
+    }
+    fn avm(&mut self, node: &Term) -> Avm {
+        let mut avm = Avm::new();
+        if self.shared.contains(node) {
+            let tag = self.tags.len() + 1;
+            self.tags.insert(node.clone(), tag);
+            avm.tag = Some(tag);
+        }
+        if self.with_vars && node.arg.is_none() {
+            avm.var = Some(node.head.as_ref().to_owned());
+        }
+        if let Some(features) = self.graph.get(node) {
+            self.path.push(node.clone());
+            // The structures are built in alphabetical order so that a shared structure is held
+            // by its first occurrence in the output.
+            for (attr, value) in features {
+                let value = self.value(value);
+                avm.features.insert(attr.to_owned(), value);
+            }
+            self.path.pop();
+        }
+        avm
     }
 }
 
 impl fmt::Debug for Avm {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(tag) = self.tag {
+            write!(f, "[{}] ", tag)?;
+        }
+        if let Some(var) = &self.var {
+            write!(f, "<{}> ", var)?;
+        }
         write!(f, "{:?}", self.features)
     }
 }
+
+impl fmt::Display for Avm {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.to_text())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn system(equations: &[(Term, Term)]) -> RewritingSystem {
+        let mut rs = RewritingSystem::new();
+        for (lhs, rhs) in equations {
+            assert!(rs.add_rule(RewriteRule::new(lhs.clone(), rhs.clone())));
+        }
+        rs
+    }
+
+    #[test]
+    fn cycles_are_marked_unlike_reentrancies() {
+        let rs = system(&[
+            (Term::new("X", &["g1"]), Term::new("g1", &[])),
+            (Term::new("P", &["g1"]), Term::new("@a", &[])),
+        ]);
+        let avm = Avm::from_theory(&rs, "g1");
+        assert_eq!(avm.to_text(), "[1][ P  a          ]\n   [ X  [1]<cycle> ]");
+        assert_eq!(
+            avm.to_json(),
+            r##"{"#tag":1,"P":"a","X":{"#ref":1,"#cycle":true}}"##
+        );
+
+        let rs = system(&[
+            (Term::new("A", &["g1"]), Term::new("g2", &[])),
+            (Term::new("B", &["g1"]), Term::new("g2", &[])),
+            (Term::new("P", &["g2"]), Term::new("@a", &[])),
+        ]);
+        let avm = Avm::from_theory(&rs, "g1");
+        assert_eq!(avm.to_text(), "[ A  [1][ P  a ] ]\n[ B  [1]         ]");
+        assert_eq!(
+            avm.to_json(),
+            r##"{"A":{"#tag":1,"P":"a"},"B":{"#ref":1}}"##
+        );
+    }
+
+    #[test]
+    fn unification_keeps_fresh_variables_of_operands_apart() {
+        let mut avm1 = Avm::new();
+        avm1.set_avm(&["A"], Avm::new());
+        avm1.set_atom(&["B"], "x");
+        let mut avm2 = Avm::new();
+        avm2.set_avm(&["C"], Avm::new());
+        let avm = avm1.unify(&avm2).unwrap();
+        assert_eq!(avm.to_json(), r#"{"A":{},"B":"x","C":{}}"#);
+        assert!(avm1.subsumes(&avm) && avm2.subsumes(&avm));
+
+        let mut avm3 = Avm::new();
+        avm3.set_atom(&["B"], "y");
+        assert_eq!(avm1.unify(&avm3).err(), Some(UnificationError));
+    }
+
+    #[test]
+    fn setting_a_path_through_an_atom_replaces_it() {
+        let mut avm = Avm::new();
+        avm.set_atom(&["A"], "x");
+        avm.set_atom(&["A", "B"], "y");
+        assert_eq!(avm.to_json(), r#"{"A":{"B":"y"}}"#);
+        avm.set_avm(&["A", "B", "C"], Avm::new());
+        assert_eq!(avm.to_json(), r#"{"A":{"B":{"C":{}}}}"#);
+    }
+
+    #[test]
+    fn operands_subsume_their_unifier() {
+        let mut avm1 = Avm::new();
+        avm1.set_avm(&["A"], Avm::new());
+        let mut avm2 = Avm::new();
+        avm2.set_atom(&["A"], "x");
+        let avm = avm1.unify(&avm2).unwrap();
+        assert_eq!(avm.to_json(), r#"{"A":"x"}"#);
+        assert!(avm1.subsumes(&avm) && avm2.subsumes(&avm));
+        assert!(!avm.subsumes(&avm1));
+
+        let mut avm3 = Avm::new();
+        let mut shared = Avm::new();
+        shared.set_tag(Some(1));
+        avm3.set_avm(&["A"], shared);
+        avm3.set_ref(&["B"], 1);
+        let avm = avm3.unify(&avm2).unwrap();
+        assert_eq!(avm.to_json(), r#"{"A":"x","B":"x"}"#);
+        assert!(avm3.subsumes(&avm) && avm2.subsumes(&avm));
+        let mut avm4 = Avm::new();
+        avm4.set_atom(&["A"], "x");
+        avm4.set_atom(&["B"], "y");
+        assert!(!avm3.subsumes(&avm4));
+    }
+
+    // An AVM with a reentrancy, a cycle, an unvalued feature and an atom with a character special in LaTeX.
+    fn shared_avm() -> Avm {
+        let rs = system(&[
+            (Term::new("SUBJ", &["g1"]), Term::new("g2", &[])),
+            (Term::new("TOPIC", &["g1"]), Term::new("g2", &[])),
+            (Term::new("SELF", &["g2"]), Term::new("g2", &[])),
+            (Term::new("PRED", &["g2"]), Term::new("@dog_1", &[])),
+            (Term::new("PRED", &["g1"]), Term::new("@bark", &[])),
+            (Term::new("ADJ", &["g1"]), Term::new("g3", &[])),
+        ]);
+        Avm::from_theory(&rs, "g1")
+    }
+
+    #[test]
+    fn constraints_describe_the_avm_they_are_extracted_from() {
+        let avm = shared_avm();
+        let constraints = avm.to_constraints("f");
+        assert_eq!(
+            format!("{:?}", constraints),
+            "[ADJ(f) = f#0, PRED(f) = @bark, PRED(SUBJ(f)) = @dog_1, \
+             SUBJ(f) = SELF(SUBJ(f)), SUBJ(f) = TOPIC(f)]"
+        );
+        let rs = system(
+            &constraints
+                .iter()
+                .map(|c| match c {
+                    Constraint::Equal(t1, t2) => (t1.clone(), t2.clone()),
+                })
+                .collect::<Vec<_>>(),
+        );
+        assert_eq!(Avm::from_theory(&rs, "f").to_json(), avm.to_json());
+    }
+
+    #[test]
+    fn latex_shows_tags_and_escapes_special_characters() {
+        assert_eq!(
+            shared_avm().to_latex(),
+            "\\avm{[ ADJ & [ ] \\\\ PRED & bark \\\\ \
+             SUBJ & \\1 [ PRED & dog\\_1 \\\\ SELF & \\1 ] \\\\ TOPIC & \\1 ]}"
+        );
+    }
+
+    #[test]
+    fn dot_draws_shared_structures_once() {
+        assert_eq!(
+            shared_avm().to_dot(),
+            "digraph avm {
+  n1 [label=\"\", shape=circle];
+  n2 [label=\"\", shape=circle];
+  n1 -> n2 [label=\"ADJ\"];
+  n3 [label=\"bark\", shape=plaintext];
+  n1 -> n3 [label=\"PRED\"];
+  nt1 [label=\"[1]\", shape=circle];
+  n4 [label=\"dog_1\", shape=plaintext];
+  nt1 -> n4 [label=\"PRED\"];
+  nt1 -> nt1 [label=\"SELF\"];
+  n1 -> nt1 [label=\"SUBJ\"];
+  n1 -> nt1 [label=\"TOPIC\"];
+}
+"
+        );
+    }
+}
@@ -1,22 +1,127 @@
 use crate::avm::*;
 use crate::grammar::*;
 use crate::rewr::*;
-use std::cell::Cell;
+use std::cell::RefCell;
 use std::cmp::Ordering;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::fmt;
 use std::rc::Rc;
 
+/// A set of chart positions, each standing for the input between it and the next position.
+#[derive(Clone, PartialEq, Eq)]
+pub struct Coverage {
+    bits: Vec<u64>,
+}
+
+impl Coverage {
+    /// Creates the coverage of the input between two positions (ignoring negative positions).
+    pub fn span(start: i32, end: i32) -> Self {
+        let mut coverage = Self { bits: Vec::new() };
+        for pos in start.max(0)..end {
+            let (i, bit) = (pos as usize / 64, pos as usize % 64);
+            if coverage.bits.len() <= i {
+                coverage.bits.resize(i + 1, 0);
+            }
+            coverage.bits[i] |= 1 << bit;
+        }
+        coverage
+    }
+    /// Checks whether the position is covered.
+    pub fn contains(&self, pos: i32) -> bool {
+        let (i, bit) = (pos as usize / 64, pos as usize % 64);
+        pos >= 0 && self.bits.get(i).is_some_and(|bits| bits & (1 << bit) != 0)
+    }
+    /// Returns the number of covered positions.
+    pub fn len(&self) -> usize {
+        self.bits
+            .iter()
+            .map(|bits| bits.count_ones() as usize)
+            .sum()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+    /// Returns the covered positions in ascending order.
+    pub fn positions(&self) -> Vec<i32> {
+        let mut positions = Vec::new();
+        for (i, bits) in self.bits.iter().enumerate() {
+            for bit in 0..64 {
+                if bits & (1 << bit) != 0 {
+                    positions.push((i * 64 + bit) as i32);
+                }
+            }
+        }
+        positions
+    }
+    /// Checks whether the covered positions are consecutive.
+    pub fn is_contiguous(&self) -> bool {
+        let positions = self.positions();
+        match (positions.first(), positions.last()) {
+            (Some(first), Some(last)) => (last - first + 1) as usize == positions.len(),
+            _ => true,
+        }
+    }
+    /// Checks whether no position is covered by both coverages.
+    pub fn is_disjoint(&self, other: &Coverage) -> bool {
+        self.bits
+            .iter()
+            .zip(&other.bits)
+            .all(|(b1, b2)| b1 & b2 == 0)
+    }
+    /// Returns the positions covered by either coverage.
+    pub fn union(&self, other: &Coverage) -> Coverage {
+        let mut bits = self.bits.clone();
+        if bits.len() < other.bits.len() {
+            bits.resize(other.bits.len(), 0);
+        }
+        for (b1, b2) in bits.iter_mut().zip(&other.bits) {
+            *b1 |= b2;
+        }
+        Coverage { bits: bits }
+    }
+}
+
+impl fmt::Display for Coverage {
+    /// Writes the covered spans, e.g. `{0-1 3-5}`.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut spans: Vec<(i32, i32)> = Vec::new();
+        for pos in self.positions() {
+            match spans.last_mut() {
+                Some((_, end)) if *end == pos => *end = pos + 1,
+                _ => spans.push((pos, pos + 1)),
+            }
+        }
+        write!(
+            f,
+            "{{{}}}",
+            spans
+                .iter()
+                .map(|(start, end)| format!("{}-{}", start, end))
+                .collect::<Vec<_>>()
+                .join(" ")
+        )
+    }
+}
+
 /// A chart edge.
 pub struct Edge {
     pub start: i32,
     pub end: i32,
-    label: String,
+    /// The positions covered by the edge (which may be discontinuous).
+    pub coverage: Coverage,
+    pub label: String,
     pub logvar: String,
     pub theories: Vec<RewritingSystem>,
     pub level: i32,
-    pub used: Cell<bool>,
-    children: Vec<Rc<Edge>>,
+    /// The weight (log-probability) of the rule or the reading the edge has been created by.
+    pub weight: f64,
+    pub children: Vec<Rc<Edge>>,
+    /// The index of the grammar rule the edge has been created by (`None` for lexical edges).
+    pub rule: Option<usize>,
+    /// The rule items matched by the daughters.
+    pub items: Vec<Rc<RuleItem>>,
+    /// Alternative derivations of the edge packed into it by the chart.
+    pub packed: RefCell<Vec<Rc<Edge>>>,
 }
 
 impl Edge {
@@ -49,32 +154,356 @@ impl Edge {
         level: i32,
         children: Vec<Rc<Edge>>,
     ) -> Self {
+        let coverage = if children.is_empty() {
+            Coverage::span(start, end)
+        } else {
+            children.iter().fold(Coverage::span(0, 0), |coverage, e| {
+                coverage.union(&e.coverage)
+            })
+        };
         Self {
             start: start,
             end: end,
+            coverage: coverage,
             label: label.to_owned(),
             logvar: logvar.to_owned(),
             theories: theories,
             level: level,
-            used: Cell::new(false),
+            weight: 0.0,
             children: children,
+            rule: None,
+            items: Vec::new(),
+            packed: RefCell::new(Vec::new()),
+        }
+    }
+    /// Checks whether the edge covers the same input as the other edge with the same label
+    /// and the f-structures of its theories subsume each other.
+    pub fn is_equivalent(&self, other: &Edge) -> bool {
+        if self.coverage != other.coverage || self.label != other.label {
+            return false;
+        }
+        let avms1 = self
+            .theories
+            .iter()
+            .map(|t| Avm::from_theory(t, &self.logvar))
+            .collect::<Vec<_>>();
+        let avms2 = other
+            .theories
+            .iter()
+            .map(|t| Avm::from_theory(t, &other.logvar))
+            .collect::<Vec<_>>();
+        let covers = |avms1: &[Avm], avms2: &[Avm]| {
+            avms1
+                .iter()
+                .all(|a1| avms2.iter().any(|a2| a1.subsumes(a2) && a2.subsumes(a1)))
+        };
+        covers(&avms1, &avms2) && covers(&avms2, &avms1)
+    }
+    /// Returns the number of trees packed into the edge.
+    pub fn tree_count(&self) -> usize {
+        let mut count = 0;
+        for edge in [self]
+            .into_iter()
+            .chain(self.packed.borrow().iter().map(|e| e.as_ref()))
+        {
+            count += edge
+                .children
+                .iter()
+                .map(|e| e.tree_count())
+                .product::<usize>();
+        }
+        count
+    }
+    /// Returns the score of the tree of the edge, i.e. the sum of the weights of its edges (ignoring packed alternatives).
+    pub fn score(&self) -> f64 {
+        self.weight + self.children.iter().map(|e| e.score()).sum::<f64>()
+    }
+    /// Returns the score of the best tree packed into the edge (`None` if no tree can be unpacked).
+    pub fn best_score(self: &Rc<Self>) -> Option<f64> {
+        TreeEnumeration::new().tree(self, 0).map(|(score, _)| score)
+    }
+    /// Returns the best tree packed into the edge (the first one among trees with the same score).
+    pub fn best_tree(self: &Rc<Self>) -> Option<Rc<Edge>> {
+        self.n_best(1).pop()
+    }
+    /// Returns the `n` best trees packed into the edge, ordered by descending score.
+    pub fn n_best(self: &Rc<Self>, n: usize) -> Vec<Rc<Edge>> {
+        Self::n_best_of(std::slice::from_ref(self), n)
+    }
+    /// Returns the `n` best trees packed into any of the given edges, ordered by descending score.
+    /// Trees are enumerated best first, so only as many trees as needed are unpacked.
+    pub fn n_best_of(edges: &[Rc<Edge>], n: usize) -> Vec<Rc<Edge>> {
+        let mut enumeration = TreeEnumeration::new();
+        let mut trees = Vec::new();
+        for edge in edges {
+            for k in 0..n {
+                match enumeration.tree(edge, k) {
+                    Some(tree) => trees.push(tree),
+                    None => break,
+                }
+            }
+        }
+        trees.sort_by(|(s1, _), (s2, _)| s2.total_cmp(s1));
+        trees.truncate(n);
+        trees.into_iter().map(|(_, tree)| tree).collect()
+    }
+    /// Unpacks the edge, returning an edge without packed alternatives for each tree.
+    /// The theories of a mother whose daughter is replaced by an alternative (or by an unpacked tree) are rebuilt
+    /// from the theories of its daughters and the constraints of the rule items they match, so that they describe the words of the tree.
+    pub fn trees(self: &Rc<Self>) -> Vec<Rc<Edge>> {
+        let mut trees = Vec::new();
+        let derivations = [self.clone()]
+            .into_iter()
+            .chain(self.packed.borrow().iter().cloned())
+            .collect::<Vec<_>>();
+        for derivation in derivations {
+            let mut combinations: Vec<Vec<Rc<Edge>>> = vec![Vec::new()];
+            for child in &derivation.children {
+                let child_trees = child.trees();
+                let mut new_combinations = Vec::new();
+                for combination in &combinations {
+                    for tree in &child_trees {
+                        let mut combination = combination.clone();
+                        combination.push(tree.clone());
+                        new_combinations.push(combination);
+                    }
+                }
+                combinations = new_combinations;
+            }
+            for combination in combinations {
+                trees.extend(Self::unpack(&derivation, combination));
+            }
+        }
+        trees
+    }
+    /// Returns the derivation with the given unpacked daughters (unless their theories are inconsistent).
+    fn unpack(derivation: &Rc<Edge>, combination: Vec<Rc<Edge>>) -> Option<Rc<Edge>> {
+        let unchanged = combination
+            .iter()
+            .zip(&derivation.children)
+            .all(|(e1, e2)| Rc::ptr_eq(e1, e2));
+        if unchanged {
+            return Some(derivation.clone());
+        }
+        // The theories are rebuilt from an empty one with the ordering and cycle policy of the derivation's theories.
+        let mut theory = derivation.theories.first()?.clone();
+        theory.rules.clear();
+        let mut theories = vec![theory];
+        for (i, tree) in combination.iter().enumerate() {
+            let item = derivation.items.get(i)?;
+            theories = item.apply(&theories, &derivation.logvar, tree);
+            if theories.is_empty() {
+                return None;
+            }
         }
+        let mut edge = Edge::new_with_children(
+            derivation.start,
+            derivation.end,
+            &derivation.label,
+            &derivation.logvar,
+            theories,
+            derivation.level,
+            combination,
+        );
+        edge.weight = derivation.weight;
+        edge.rule = derivation.rule;
+        edge.items = derivation.items.clone();
+        Some(Rc::new(edge))
+    }
+    /// Returns the lexical edges spanned by the edge in the order of the input.
+    pub fn leaves(&self) -> Vec<&Edge> {
+        if self.children.is_empty() {
+            return vec![self];
+        }
+        let mut leaves = self
+            .children
+            .iter()
+            .flat_map(|e| e.leaves())
+            .collect::<Vec<_>>();
+        leaves.sort_by_key(|e| e.start);
+        leaves
     }
     /// Returns the linearised syntax tree represented by the edge.
-    fn tree(&self) -> String {
+    /// The coverages of discontinuous edges and of the daughters of discontinuous or reordered edges are shown
+    /// to make the interleaving visible.
+    pub fn tree(&self) -> String {
+        self.write_tree(false, false)
+    }
+    /// Returns the linearised syntax tree with the index of the rule each edge has been created by (e.g. `NP#2(N)`).
+    pub fn tree_with_rules(&self) -> String {
+        self.write_tree(false, true)
+    }
+    fn write_tree(&self, show_coverage: bool, show_rules: bool) -> String {
         let mut tree = self.label.to_owned();
+        if let Some(rule) = self.rule.filter(|_| show_rules) {
+            tree += &format!("#{}", rule);
+        }
+        let discontinuous = !self.coverage.is_contiguous();
+        if show_coverage || discontinuous {
+            tree += &self.coverage.to_string();
+        }
+        let interleaved = discontinuous
+            || self
+                .children
+                .windows(2)
+                .any(|pair| pair[0].end > pair[1].start);
         if self.children.len() > 0 {
             tree += "(";
             tree += &self
                 .children
                 .iter()
-                .map(|e| e.tree())
+                .map(|e| e.write_tree(interleaved, show_rules))
                 .collect::<Vec<_>>()
                 .join(",");
             tree += ")";
         }
         tree
     }
+    /// Returns the syntax tree and the f-structures of the edge as a graph in the DOT language.
+    /// Tree nodes show the indices of their rules and tree edges the matched rule items.
+    /// Each node of the tree is linked to the f-structure denoted by its logical variable in each theory.
+    pub fn to_dot(&self) -> String {
+        let mut dot = "digraph parse {\n  subgraph cluster_tree {\n".to_owned();
+        let mut nodes = Vec::new();
+        self.write_dot_tree(&mut dot, &mut nodes);
+        dot += "  }\n";
+        for (i, t) in self.theories.iter().enumerate() {
+            dot += &format!("  subgraph cluster_f{} {{\n", i);
+            let vars = Avm::from_theory_with_vars(t, &self.logvar)
+                .write_dot_nodes(&format!("f{}_", i), &mut dot);
+            dot += "  }\n";
+            for (id, logvar) in &nodes {
+                let var = t.norm(&Term::new(logvar, &[]));
+                if let Some(var_id) = vars.get(var.head.as_ref()).filter(|_| var.arg.is_none()) {
+                    dot += &format!("  {} -> {} [style=dashed, arrowhead=none];\n", id, var_id);
+                }
+            }
+        }
+        dot += "}\n";
+        dot
+    }
+    /// Writes the DOT nodes and edges of the syntax tree, collecting node identifiers and logical variables.
+    fn write_dot_tree(&self, dot: &mut String, nodes: &mut Vec<(String, String)>) -> String {
+        let id = format!("e{}", nodes.len() + 1);
+        nodes.push((id.to_owned(), self.logvar.to_owned()));
+        let label = match self.rule {
+            Some(rule) => format!("{} #{}", self.label, rule),
+            None => self.label.to_owned(),
+        };
+        *dot += &format!("  {} [label={:?}, shape=box];\n", id, label);
+        for (i, child) in self.children.iter().enumerate() {
+            let child_id = child.write_dot_tree(dot, nodes);
+            match self.items.get(i) {
+                Some(item) => {
+                    *dot += &format!(
+                        "  {} -> {} [label={:?}];\n",
+                        id,
+                        child_id,
+                        format!("{:?}", item)
+                    )
+                }
+                None => *dot += &format!("  {} -> {};\n", id, child_id),
+            }
+        }
+        id
+    }
+}
+
+// The trees of the edges of a packed forest, enumerated best first on demand.
+// Each edge keeps the trees found so far and the candidate combinations of daughter trees to try next,
+// so subforests shared by several edges are only enumerated once.
+struct TreeEnumeration {
+    states: HashMap<*const Edge, TreeState>,
+}
+
+struct TreeState {
+    // The trees found so far with their scores, in descending order.
+    trees: Vec<(f64, Rc<Edge>)>,
+    derivations: Vec<Rc<Edge>>,
+    // The combinations to try: their scores, derivations and the ranks of the daughter trees.
+    candidates: Vec<(f64, usize, Vec<usize>)>,
+    seen: HashSet<(usize, Vec<usize>)>,
+}
+
+impl TreeEnumeration {
+    fn new() -> Self {
+        Self {
+            states: HashMap::new(),
+        }
+    }
+    /// Returns the `k`-th best tree of the edge (starting from 0) with its score.
+    fn tree(&mut self, edge: &Rc<Edge>, k: usize) -> Option<(f64, Rc<Edge>)> {
+        let mut state = match self.states.remove(&Rc::as_ptr(edge)) {
+            Some(state) => state,
+            None => self.start(edge),
+        };
+        while state.trees.len() <= k {
+            let mut best = None;
+            for (i, (score, _, _)) in state.candidates.iter().enumerate() {
+                if best.is_none_or(|best: usize| *score > state.candidates[best].0) {
+                    best = Some(i);
+                }
+            }
+            let (score, index, ranks) = match best {
+                Some(best) => state.candidates.remove(best),
+                None => break,
+            };
+            let derivation = state.derivations[index].clone();
+            let combination = derivation
+                .children
+                .iter()
+                .zip(&ranks)
+                .map(|(child, &rank)| self.tree(child, rank).unwrap().1)
+                .collect();
+            if let Some(tree) = Edge::unpack(&derivation, combination) {
+                state.trees.push((score, tree));
+            }
+            for i in 0..ranks.len() {
+                let mut next = ranks.clone();
+                next[i] += 1;
+                if state.seen.contains(&(index, next.clone())) {
+                    continue;
+                }
+                if let Some(score) = self.score(&derivation, &next) {
+                    state.seen.insert((index, next.clone()));
+                    state.candidates.push((score, index, next));
+                }
+            }
+        }
+        let tree = state.trees.get(k).cloned();
+        self.states.insert(Rc::as_ptr(edge), state);
+        tree
+    }
+    /// Creates the state of an edge, the best combination of each derivation being the first candidate.
+    fn start(&mut self, edge: &Rc<Edge>) -> TreeState {
+        let derivations = [edge.clone()]
+            .into_iter()
+            .chain(edge.packed.borrow().iter().cloned())
+            .collect::<Vec<_>>();
+        let mut state = TreeState {
+            trees: Vec::new(),
+            derivations: Vec::new(),
+            candidates: Vec::new(),
+            seen: HashSet::new(),
+        };
+        for (index, derivation) in derivations.into_iter().enumerate() {
+            let ranks = vec![0; derivation.children.len()];
+            if let Some(score) = self.score(&derivation, &ranks) {
+                state.seen.insert((index, ranks.clone()));
+                state.candidates.push((score, index, ranks));
+            }
+            state.derivations.push(derivation);
+        }
+        state
+    }
+    /// Returns the score of a derivation with the daughter trees of the given ranks (unless there are no such trees).
+    fn score(&mut self, derivation: &Edge, ranks: &[usize]) -> Option<f64> {
+        let mut score = derivation.weight;
+        for (child, &rank) in derivation.children.iter().zip(ranks) {
+            score += self.tree(child, rank)?.0;
+        }
+        Some(score)
+    }
 }
 
 impl fmt::Debug for Edge {
@@ -89,7 +518,7 @@ impl fmt::Debug for Edge {
             self.start,
             self.label,
             self.end,
-            self.tree(),
+            self.tree_with_rules(),
             avms.iter()
                 .map(|avm| format!("{:?}", avm))
                 .collect::<Vec<_>>()
@@ -98,18 +527,64 @@ impl fmt::Debug for Edge {
     }
 }
 
+/// An error returned when an edge can't be added to a chart.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ChartError {
+    pub start: i32,
+    pub end: i32,
+}
+
+impl fmt::Display for ChartError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "negative chart position in span {}-{}",
+            self.start, self.end
+        )
+    }
+}
+
+// A cover of the input up to a position by fragments: the number of skipped positions, the number of edges,
+// their total level and the last edge (`None` if the position before has been skipped).
+type Cover = (usize, usize, i32, Option<Rc<Edge>>);
+
 /// A chart for context-free parsing.
+/// Edges are indexed by their positions and labels, and kept in insertion order so that parsing is deterministic.
+/// Positions must not be negative.
 pub struct Chart {
-    edges: HashMap<i32, Vec<Rc<Edge>>>,
+    // The edges indexed by start position and label.
+    starting: Vec<BTreeMap<String, Vec<Rc<Edge>>>>,
+    // The edges indexed by end position and label.
+    ending: Vec<BTreeMap<String, Vec<Rc<Edge>>>>,
+    packing: bool,
 }
 
 impl Chart {
     /// Creates a new empty chart.
     pub fn new() -> Self {
         Self {
-            edges: HashMap::new(),
+            starting: Vec::new(),
+            ending: Vec::new(),
+            packing: false,
         }
     }
+    /// Enables or disables local ambiguity packing.
+    /// Edges equivalent to an edge already in the chart are then packed into it instead of being added,
+    /// except for edges derived from it, which are dropped.
+    pub fn set_packing(&mut self, packing: bool) {
+        self.packing = packing;
+    }
+    /// Returns the edges with the given label in an index at the given position.
+    fn indexed<'a>(
+        index: &'a [BTreeMap<String, Vec<Rc<Edge>>>],
+        pos: i32,
+        label: &str,
+    ) -> &'a [Rc<Edge>] {
+        index
+            .get(pos as usize)
+            .and_then(|edges| edges.get(label))
+            .map_or(&[], |edges| edges.as_slice())
+    }
     fn find_paths_from(
         &self,
         start: i32,
@@ -123,22 +598,18 @@ impl Chart {
             if can_skip && item.skippable {
                 self.find_paths_from(start, &pattern[1..], edges, items, true, cb);
             }
-            if let Some(node_edges) = self.edges.get(&start) {
-                for edge in node_edges {
-                    if edge.label == item.symbol {
-                        edges.push(edge.clone());
-                        items.push(item.clone());
-                        self.find_paths_from(edge.end, &pattern[1..], edges, items, true, cb);
-                        edges.pop();
-                        items.pop();
-                        if item.repeatable {
-                            edges.push(edge.clone());
-                            items.push(item.clone());
-                            self.find_paths_from(edge.end, pattern, edges, items, false, cb);
-                            edges.pop();
-                            items.pop();
-                        }
-                    }
+            for edge in Self::indexed(&self.starting, start, &item.symbol) {
+                edges.push(edge.clone());
+                items.push(item.clone());
+                self.find_paths_from(edge.end, &pattern[1..], edges, items, true, cb);
+                edges.pop();
+                items.pop();
+                if item.repeatable {
+                    edges.push(edge.clone());
+                    items.push(item.clone());
+                    self.find_paths_from(edge.end, pattern, edges, items, false, cb);
+                    edges.pop();
+                    items.pop();
                 }
             }
         } else {
@@ -148,6 +619,177 @@ impl Chart {
             }
         }
     }
+    /// Finds paths ending at the given position matching the given pattern.
+    /// The callback receives the edges and items in reverse order.
+    fn find_paths_to(
+        &self,
+        end: i32,
+        pattern: &[Rc<RuleItem>],
+        edges: &mut Vec<Rc<Edge>>,
+        items: &mut Vec<Rc<RuleItem>>,
+        can_skip: bool,
+        cb: &mut dyn FnMut(&Vec<Rc<Edge>>, &Vec<Rc<RuleItem>>),
+    ) {
+        if let Some((item, rest)) = pattern.split_last() {
+            if can_skip && item.skippable {
+                self.find_paths_to(end, rest, edges, items, true, cb);
+            }
+            for edge in Self::indexed(&self.ending, end, &item.symbol) {
+                edges.push(edge.clone());
+                items.push(item.clone());
+                self.find_paths_to(edge.start, rest, edges, items, true, cb);
+                edges.pop();
+                items.pop();
+                if item.repeatable {
+                    edges.push(edge.clone());
+                    items.push(item.clone());
+                    self.find_paths_to(edge.start, pattern, edges, items, false, cb);
+                    edges.pop();
+                    items.pop();
+                }
+            }
+        } else {
+            cb(edges, items);
+        }
+    }
+    /// Finds all non-empty paths in the chart ending at the given position and matching the given pattern.
+    pub fn find_paths_ending_at(
+        &self,
+        end: i32,
+        pattern: &[Rc<RuleItem>],
+        cb: &mut dyn FnMut(&Vec<Rc<Edge>>, &Vec<Rc<RuleItem>>),
+    ) {
+        let mut edges = Vec::new();
+        let mut items = Vec::new();
+        self.find_paths_to(
+            end,
+            pattern,
+            &mut edges,
+            &mut items,
+            true,
+            &mut |edges, items| {
+                if edges.len() > 0 {
+                    let edges = edges.iter().rev().cloned().collect();
+                    let items = items.iter().rev().cloned().collect();
+                    cb(&edges, &items);
+                }
+            },
+        );
+    }
+    /// Finds all paths in the chart matching the given pattern that contain the given edge.
+    /// The edge is matched against each item of the pattern with its symbol, the rest of the path
+    /// being searched for to the left and to the right of it.
+    pub fn find_paths_with(
+        &self,
+        edge: &Rc<Edge>,
+        pattern: &[Rc<RuleItem>],
+        cb: &mut dyn FnMut(&Vec<Rc<Edge>>, &Vec<Rc<RuleItem>>),
+    ) {
+        for (i, item) in pattern.iter().enumerate() {
+            if item.symbol != edge.label {
+                continue;
+            }
+            let mut find_suffixes = |left_edges: &Vec<Rc<Edge>>, left_items: &Vec<Rc<RuleItem>>| {
+                let mut edges = left_edges.iter().rev().cloned().collect::<Vec<_>>();
+                let mut items = left_items.iter().rev().cloned().collect::<Vec<_>>();
+                edges.push(edge.clone());
+                items.push(item.clone());
+                self.find_paths_from(
+                    edge.end,
+                    &pattern[i + 1..],
+                    &mut edges,
+                    &mut items,
+                    true,
+                    cb,
+                );
+                if item.repeatable {
+                    self.find_paths_from(
+                        edge.end,
+                        &pattern[i..],
+                        &mut edges,
+                        &mut items,
+                        false,
+                        cb,
+                    );
+                }
+            };
+            let (mut edges, mut items) = (Vec::new(), Vec::new());
+            self.find_paths_to(
+                edge.start,
+                &pattern[..i],
+                &mut edges,
+                &mut items,
+                true,
+                &mut find_suffixes,
+            );
+            if item.repeatable {
+                self.find_paths_to(
+                    edge.start,
+                    &pattern[..=i],
+                    &mut edges,
+                    &mut items,
+                    false,
+                    &mut find_suffixes,
+                );
+            }
+        }
+    }
+    /// Finds all combinations of edges in the chart matching the given pattern that contain the given edge,
+    /// the edges being in any order (subject to the given precedence constraints) and not necessarily adjacent.
+    pub fn find_combinations_with(
+        &self,
+        edge: &Rc<Edge>,
+        pattern: &[Rc<RuleItem>],
+        precedence: &[(usize, usize)],
+        cb: &mut dyn FnMut(&Vec<Rc<Edge>>, &Vec<Rc<RuleItem>>),
+    ) {
+        self.find_combinations_from(edge, pattern, precedence, 0, &mut Vec::new(), cb);
+    }
+    /// Extends a combination of edges (paired with the indices of their items) from the given item on.
+    fn find_combinations_from(
+        &self,
+        edge: &Rc<Edge>,
+        pattern: &[Rc<RuleItem>],
+        precedence: &[(usize, usize)],
+        index: usize,
+        matched: &mut Vec<(Rc<Edge>, usize)>,
+        cb: &mut dyn FnMut(&Vec<Rc<Edge>>, &Vec<Rc<RuleItem>>),
+    ) {
+        let item = match pattern.get(index) {
+            Some(item) => item,
+            None => {
+                if matched.iter().any(|(e, _)| Rc::ptr_eq(e, edge)) {
+                    let edges = matched.iter().map(|(e, _)| e.clone()).collect();
+                    let items = matched.iter().map(|&(_, i)| pattern[i].clone()).collect();
+                    cb(&edges, &items);
+                }
+                return;
+            }
+        };
+        let repeated = matched.last().map(|(_, i)| *i) == Some(index);
+        if item.skippable || repeated {
+            self.find_combinations_from(edge, pattern, precedence, index + 1, matched, cb);
+        }
+        for pos_edges in &self.starting {
+            for candidate in pos_edges.get(&item.symbol).into_iter().flatten() {
+                if repeated && candidate.start < matched.last().unwrap().0.end {
+                    continue;
+                }
+                let compatible = matched.iter().all(|(e, i)| {
+                    e.coverage.is_disjoint(&candidate.coverage)
+                        && (!precedence.contains(&(*i, index)) || e.end <= candidate.start)
+                        && (!precedence.contains(&(index, *i)) || candidate.end <= e.start)
+                });
+                if !compatible {
+                    continue;
+                }
+                matched.push((candidate.clone(), index));
+                let next = if item.repeatable { index } else { index + 1 };
+                self.find_combinations_from(edge, pattern, precedence, next, matched, cb);
+                matched.pop();
+            }
+        }
+    }
     /// Finds all paths in the chart matching the given pattern.
     pub fn find_paths(
         &self,
@@ -156,25 +798,202 @@ impl Chart {
     ) {
         let mut edges = Vec::new();
         let mut items = Vec::new();
-        for (&i, _) in &self.edges {
-            self.find_paths_from(i, pattern, &mut edges, &mut items, true, cb);
+        for (i, node_edges) in self.starting.iter().enumerate() {
+            if !node_edges.is_empty() {
+                self.find_paths_from(i as i32, pattern, &mut edges, &mut items, true, cb);
+            }
         }
     }
+    /// Returns the edges spanning the given positions.
+    pub fn edges_between(&self, start: i32, end: i32) -> Vec<Rc<Edge>> {
+        let mut v = Vec::new();
+        for edges in self
+            .starting
+            .get(start as usize)
+            .into_iter()
+            .flat_map(|e| e.values())
+        {
+            for edge in edges {
+                if edge.end == end {
+                    v.push(edge.clone());
+                }
+            }
+        }
+        v
+    }
+    /// Returns the fewest contiguous edges covering the input between the given positions one after another
+    /// (preferring edges of higher levels among covers of the same length).
+    /// Positions no edge can cover are skipped, covers leaving out the fewest positions being preferred.
+    pub fn fragments(&self, start: i32, end: i32) -> Vec<Rc<Edge>> {
+        // The best cover found for each position.
+        let mut best: BTreeMap<i32, Cover> = BTreeMap::new();
+        best.insert(start, (0, 0, 0, None));
+        for pos in start..end {
+            let (skipped, count, level) = match best.get(&pos) {
+                Some((skipped, count, level, _)) => (*skipped, *count, *level),
+                None => continue,
+            };
+            let mut candidates = vec![(pos + 1, (skipped + 1, count, level, None))];
+            for edges in self
+                .starting
+                .get(pos as usize)
+                .into_iter()
+                .flat_map(|e| e.values())
+            {
+                for edge in edges {
+                    if edge.end <= end && edge.coverage.is_contiguous() {
+                        let cover = (skipped, count + 1, level + edge.level, Some(edge.clone()));
+                        candidates.push((edge.end, cover));
+                    }
+                }
+            }
+            for (pos, cover) in candidates {
+                let better = match best.get(&pos) {
+                    Some((skipped2, count2, level2, _)) => {
+                        (cover.0, cover.1, -cover.2) < (*skipped2, *count2, -level2)
+                    }
+                    None => true,
+                };
+                if better {
+                    best.insert(pos, cover);
+                }
+            }
+        }
+        let mut edges = Vec::new();
+        let mut pos = end;
+        while pos > start {
+            match best.get(&pos) {
+                Some((_, _, _, Some(edge))) => {
+                    edges.insert(0, edge.clone());
+                    pos = edge.start;
+                }
+                _ => pos -= 1,
+            }
+        }
+        edges
+    }
+    /// Removes all edges from the chart.
+    pub fn clear(&mut self) {
+        self.starting.clear();
+        self.ending.clear();
+    }
     /// Adds a new edge to the chart.
-    pub fn add_edge(&mut self, edge: Rc<Edge>) {
-        let edges = self.edges.entry(edge.start).or_insert(Vec::new());
-        edges.push(edge);
+    /// Returns `false` if the edge has been packed into an equivalent edge instead
+    /// (or dropped, if the equivalent edge lies below it), and an error if it has a negative position.
+    pub fn add_edge(&mut self, edge: Rc<Edge>) -> Result<bool, ChartError> {
+        if edge.start < 0 || edge.end < 0 {
+            return Err(ChartError {
+                start: edge.start,
+                end: edge.end,
+            });
+        }
+        if self.packing {
+            let mut below = None;
+            for other in Self::indexed(&self.starting, edge.start, &edge.label) {
+                if other.is_equivalent(&edge) {
+                    // Packing the edge into one of its descendants (e.g. the daughter of a unary rule
+                    // with the same f-structure) would make the forest cyclic.
+                    let below = below.get_or_insert_with(|| {
+                        let mut below = HashSet::new();
+                        Self::find_edges_below(&edge, &mut below);
+                        below
+                    });
+                    if !below.contains(&Rc::as_ptr(other)) {
+                        other.packed.borrow_mut().push(edge);
+                    }
+                    return Ok(false);
+                }
+            }
+        }
+        for (index, pos) in [
+            (&mut self.starting, edge.start),
+            (&mut self.ending, edge.end),
+        ] {
+            if index.len() <= pos as usize {
+                index.resize_with(pos as usize + 1, BTreeMap::new);
+            }
+            index[pos as usize]
+                .entry(edge.label.to_owned())
+                .or_insert_with(Vec::new)
+                .push(edge.clone());
+        }
+        Ok(true)
+    }
+    /// Removes an edge from the chart (but not the edges built from it), returning whether it was in the chart.
+    pub fn remove_edge(&mut self, edge: &Rc<Edge>) -> bool {
+        let mut found = false;
+        for (index, pos) in [
+            (&mut self.starting, edge.start),
+            (&mut self.ending, edge.end),
+        ] {
+            if let Some(edges) = index
+                .get_mut(pos as usize)
+                .and_then(|edges| edges.get_mut(&edge.label))
+            {
+                let len = edges.len();
+                edges.retain(|e| !Rc::ptr_eq(e, edge));
+                found = edges.len() < len;
+            }
+        }
+        found
     }
     /// Returns all edges in the chart.
-    pub fn all_edges(&self, only_unused: bool) -> Vec<Rc<Edge>> {
+    pub fn all_edges(&self) -> Vec<Rc<Edge>> {
         let mut v = Vec::new();
-        for (_, edges) in &self.edges {
-            for edge in edges {
-                if !only_unused || edge.used.get() == false {
-                    v.push(edge.clone());
+        for node_edges in &self.starting {
+            for edges in node_edges.values() {
+                v.extend(edges.iter().cloned());
+            }
+        }
+        Self::sort_edges(&mut v);
+        v
+    }
+    /// Returns the maximal edges of the chart with respect to complete analyses, i.e. to the edges labelled
+    /// with the start symbol covering the positions between the given ones without gaps.
+    /// If there are complete analyses, they are the maximal edges: any other edge either lies below one of them
+    /// or is a dead end not needed by any of them. Otherwise, the maximal edges are the edges not lying below
+    /// any other edge in the chart. Edges below an edge are found by walking down its derivations, including the
+    /// alternatives packed into it. Edges dropped by pruning don't count, so this is well-defined only after parsing.
+    pub fn maximal_edges(&self, start_symbol: &str, start: i32, end: i32) -> Vec<Rc<Edge>> {
+        let edges = self.all_edges();
+        let complete = edges
+            .iter()
+            .filter(|edge| {
+                edge.start == start
+                    && edge.end == end
+                    && edge.label == start_symbol
+                    && edge.coverage.is_contiguous()
+            })
+            .cloned()
+            .collect::<Vec<_>>();
+        if !complete.is_empty() {
+            return complete;
+        }
+        let mut below = HashSet::new();
+        for edge in &edges {
+            Self::find_edges_below(edge, &mut below);
+        }
+        edges
+            .into_iter()
+            .filter(|edge| !below.contains(&Rc::as_ptr(edge)))
+            .collect()
+    }
+    /// Collects the edges lying below the given edge in any of its derivations.
+    fn find_edges_below(edge: &Rc<Edge>, below: &mut HashSet<*const Edge>) {
+        let derivations = [edge.clone()]
+            .into_iter()
+            .chain(edge.packed.borrow().iter().cloned())
+            .collect::<Vec<_>>();
+        for derivation in derivations {
+            for child in &derivation.children {
+                if below.insert(Rc::as_ptr(child)) {
+                    Self::find_edges_below(child, below);
                 }
             }
         }
+    }
+    /// Sorts edges by start position, then by decreasing end position and level, then by label.
+    fn sort_edges(v: &mut [Rc<Edge>]) {
         v.sort_by(|e1, e2| {
             let c = e1.start.cmp(&e2.start);
             if c != Ordering::Equal {
@@ -190,6 +1009,165 @@ impl Chart {
             }
             e1.label.cmp(&e2.label)
         });
-        v
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn edges_with_negative_positions_are_rejected() {
+        let mut chart = Chart::new();
+        let edge = Rc::new(Edge::new(-1, 1, "N", "w0", vec![Vec::new()]));
+        assert_eq!(chart.add_edge(edge), Err(ChartError { start: -1, end: 1 }));
+        assert!(chart.all_edges().is_empty());
+        let edge = Rc::new(Edge::new(0, 1, "N", "w0", vec![Vec::new()]));
+        assert_eq!(chart.add_edge(edge), Ok(true));
+        assert_eq!(chart.all_edges().len(), 1);
+    }
+
+    fn term(head: &str, args: &[&str]) -> Term {
+        Term::new(head, args)
+    }
+
+    // A lexical edge with the given weight whose theory also constrains a variable outside its f-structure.
+    fn lexical_edge(start: i32, logvar: &str, weight: f64, other: &str) -> Rc<Edge> {
+        let mut edge = Edge::new(
+            start,
+            start + 1,
+            "Y",
+            logvar,
+            vec![vec![
+                Constraint::Equal(term("PRED", &["*"]), term("@y", &[])),
+                Constraint::Equal(term("Q", &["v"]), term(other, &[])),
+            ]],
+        );
+        edge.weight = weight;
+        Rc::new(edge)
+    }
+
+    // Combines edges with rule items equating an attribute of the mother with each daughter.
+    fn combine(label: &str, logvar: &str, edges: &[Rc<Edge>], attrs: &[&str]) -> Edge {
+        let items = attrs
+            .iter()
+            .map(|attr| {
+                let equation = Constraint::Equal(term(attr, &["*"]), term(".", &[]));
+                Rc::new(RuleItem::new("Y", vec![vec![equation]]))
+            })
+            .collect::<Vec<_>>();
+        let mut theories = vec![RewritingSystem::new()];
+        for (edge, item) in edges.iter().zip(&items) {
+            theories = item.apply(&theories, logvar, edge);
+        }
+        let start = edges.first().unwrap().start;
+        let end = edges.last().unwrap().end;
+        let mut edge =
+            Edge::new_with_children(start, end, label, logvar, theories, 1, edges.to_owned());
+        edge.items = items;
+        edge
+    }
+
+    #[test]
+    fn n_best_trees_skip_inconsistent_combinations() {
+        let mut chart = Chart::new();
+        chart.set_packing(true);
+        // The better alternatives of the first word are inconsistent with the second word.
+        let y1 = lexical_edge(0, "y1", -3.0, "@1");
+        let y2 = lexical_edge(0, "y2", -1.0, "@2");
+        let y3 = lexical_edge(0, "y3", -2.0, "@3");
+        let z = lexical_edge(1, "z", 0.0, "@1");
+        for edge in [&y1, &y2, &y3, &z] {
+            chart.add_edge(edge.clone()).unwrap();
+        }
+        assert_eq!(chart.all_edges().len(), 2);
+        let x = Rc::new(combine("X", "g1", &[y1.clone(), z.clone()], &["A", "B"]));
+        assert_eq!(x.tree_count(), 3);
+        let scores = x
+            .trees()
+            .iter()
+            .map(|tree| tree.score())
+            .collect::<Vec<_>>();
+        assert_eq!(scores, [-3.0]);
+        assert_eq!(x.best_score(), Some(-3.0));
+        let best = x.best_tree().unwrap();
+        assert_eq!(best.score(), -3.0);
+        assert_eq!(best.children[0].logvar, "y1");
+        assert_eq!(x.n_best(2).len(), 1);
+    }
+
+    #[test]
+    fn n_best_trees_are_ordered_by_score() {
+        let mut chart = Chart::new();
+        chart.set_packing(true);
+        let ys = [-3.0, -1.0, -2.0]
+            .iter()
+            .enumerate()
+            .map(|(i, w)| lexical_edge(0, &format!("y{}", i), *w, "@1"))
+            .collect::<Vec<_>>();
+        let zs = [-0.5, -1.5]
+            .iter()
+            .enumerate()
+            .map(|(i, w)| lexical_edge(1, &format!("z{}", i), *w, "@1"))
+            .collect::<Vec<_>>();
+        for edge in ys.iter().chain(&zs) {
+            chart.add_edge(edge.clone()).unwrap();
+        }
+        let x = Rc::new(combine(
+            "X",
+            "g1",
+            &[ys[0].clone(), zs[0].clone()],
+            &["A", "B"],
+        ));
+        assert_eq!(x.tree_count(), 6);
+        let mut all = x.trees().iter().map(|t| t.score()).collect::<Vec<_>>();
+        all.sort_by(|s1, s2| s2.total_cmp(s1));
+        assert_eq!(all, [-1.5, -2.5, -2.5, -3.5, -3.5, -4.5]);
+        let best = x.n_best(4).iter().map(|t| t.score()).collect::<Vec<_>>();
+        assert_eq!(best, all[..4]);
+        assert_eq!(x.best_score(), Some(-1.5));
+        let best = x.best_tree().unwrap();
+        assert_eq!(
+            (
+                best.children[0].logvar.as_str(),
+                best.children[1].logvar.as_str()
+            ),
+            ("y1", "z0")
+        );
+    }
+
+    #[test]
+    fn dot_links_tree_nodes_to_their_f_structures() {
+        let y = lexical_edge(0, "y", 0.0, "@1");
+        let z = lexical_edge(1, "z", 0.0, "@1");
+        let mut x = combine("X", "g1", &[y, z], &["A", "B"]);
+        x.rule = Some(3);
+        assert_eq!(
+            x.to_dot(),
+            r#"digraph parse {
+  subgraph cluster_tree {
+  e1 [label="X #3", shape=box];
+  e2 [label="Y", shape=box];
+  e1 -> e2 [label="Y"];
+  e3 [label="Y", shape=box];
+  e1 -> e3 [label="Y"];
+  }
+  subgraph cluster_f0 {
+  f0_1 [label="g1", shape=circle];
+  f0_2 [label="y", shape=circle];
+  f0_3 [label="y", shape=plaintext];
+  f0_2 -> f0_3 [label="PRED"];
+  f0_1 -> f0_2 [label="A"];
+  f0_4 [label="z", shape=circle];
+  f0_5 [label="y", shape=plaintext];
+  f0_4 -> f0_5 [label="PRED"];
+  f0_1 -> f0_4 [label="B"];
+  }
+  e1 -> f0_1 [style=dashed, arrowhead=none];
+  e2 -> f0_2 [style=dashed, arrowhead=none];
+  e3 -> f0_4 [style=dashed, arrowhead=none];
+}
+"#
+        );
     }
 }
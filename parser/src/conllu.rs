@@ -0,0 +1,201 @@
+use crate::slot::*;
+use std::collections::HashMap;
+
+/// A mapping of categories, features and grammatical functions to Universal Dependencies.
+pub struct UdMapping {
+    /// Maps categories to UPOS tags (`X` is used for unmapped ones).
+    pub upos: HashMap<String, String>,
+    /// Maps attributes to UD features (unmapped attributes aren't exported).
+    pub features: HashMap<String, String>,
+    /// Maps atomic values to values of UD features (unmapped values are exported as they are).
+    pub values: HashMap<String, String>,
+    /// Maps grammatical functions to UD relations (`dep` is used for unmapped ones).
+    pub relations: HashMap<String, String>,
+    /// The relation of words sharing the f-structure of their head.
+    pub cohead_relation: String,
+}
+
+impl UdMapping {
+    pub fn new() -> Self {
+        Self {
+            upos: HashMap::new(),
+            features: HashMap::new(),
+            values: HashMap::new(),
+            relations: HashMap::new(),
+            cohead_relation: "aux".to_owned(),
+        }
+    }
+}
+
+impl Default for UdMapping {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SlotAnalysis {
+    /// Returns the analysis as a CoNLL-U sentence.
+    /// The forms of the words are taken from the given slice (`_` is used for missing ones).
+    /// The top word is the root, or the first word without a head if there is no top word.
+    /// Other words without a head are attached to the root as `dep`.
+    pub fn to_conllu(&self, forms: &[&str], mapping: &UdMapping) -> String {
+        let root = self.top.or_else(|| {
+            self.words
+                .iter()
+                .find(|word| word.head.is_none())
+                .map(|word| word.index)
+        });
+        let mut conllu = String::new();
+        if !forms.is_empty() {
+            conllu += &format!("# text = {}\n", forms.join(" "));
+        }
+        for word in &self.words {
+            let form = forms.get(word.index - 1).cloned().unwrap_or("_");
+            let lemma = word.lemma.as_deref().unwrap_or("_");
+            let upos = mapping
+                .upos
+                .get(&word.label)
+                .map_or("X", |upos| upos.as_str());
+            let mut feats = word
+                .features
+                .iter()
+                .filter_map(|(attr, value)| {
+                    mapping.features.get(attr).map(|feature| {
+                        let value = mapping.values.get(value).unwrap_or(value);
+                        format!("{}={}", feature, value)
+                    })
+                })
+                .collect::<Vec<_>>();
+            feats.sort_by_key(|feat| feat.to_lowercase());
+            let feats = if feats.is_empty() {
+                "_".to_owned()
+            } else {
+                feats.join("|")
+            };
+            let (head, deprel) = match (word.head, &word.slot) {
+                (Some(head), Some(slot)) => (
+                    head,
+                    mapping
+                        .relations
+                        .get(slot)
+                        .map_or("dep", |rel| rel.as_str()),
+                ),
+                (Some(head), None) => (head, mapping.cohead_relation.as_str()),
+                (None, _) if root == Some(word.index) => (0, "root"),
+                (None, _) => (root.unwrap_or(0), "dep"),
+            };
+            conllu += &format!(
+                "{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t_\t_\n",
+                word.index, form, lemma, upos, word.label, feats, head, deprel
+            );
+        }
+        conllu += "\n";
+        conllu
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn word(
+        index: usize,
+        label: &str,
+        lemma: Option<&str>,
+        features: &[(&str, &str)],
+        head: Option<usize>,
+        slot: Option<&str>,
+    ) -> SlotWord {
+        SlotWord {
+            index: index,
+            label: label.to_owned(),
+            lemma: lemma.map(|lemma| lemma.to_owned()),
+            features: features
+                .iter()
+                .map(|(attr, value)| (attr.to_string(), value.to_string()))
+                .collect(),
+            head: head,
+            slot: slot.map(|slot| slot.to_owned()),
+            frame: Vec::new(),
+        }
+    }
+
+    fn mapping() -> UdMapping {
+        let mut mapping = UdMapping::new();
+        for (label, upos) in [("N", "NOUN"), ("V", "VERB"), ("AUX", "AUX")] {
+            mapping.upos.insert(label.to_owned(), upos.to_owned());
+        }
+        for (attr, feature) in [("NUM", "Number"), ("TENSE", "Tense"), ("CASE", "Case")] {
+            mapping.features.insert(attr.to_owned(), feature.to_owned());
+        }
+        for (value, ud_value) in [("sg", "Sing"), ("fut", "Fut")] {
+            mapping.values.insert(value.to_owned(), ud_value.to_owned());
+        }
+        mapping
+            .relations
+            .insert("SUBJ".to_owned(), "nsubj".to_owned());
+        mapping
+    }
+
+    #[test]
+    fn columns_are_mapped_to_universal_dependencies() {
+        let analysis = SlotAnalysis {
+            words: vec![
+                word(
+                    1,
+                    "N",
+                    Some("dog"),
+                    &[("NUM", "sg"), ("CASE", "nom")],
+                    Some(3),
+                    Some("SUBJ"),
+                ),
+                word(2, "AUX", None, &[("TENSE", "fut")], Some(3), None),
+                word(
+                    3,
+                    "V",
+                    Some("bark"),
+                    &[("TENSE", "fut"), ("MOOD", "ind")],
+                    None,
+                    None,
+                ),
+                word(4, "ADV", Some("loudly"), &[], Some(3), Some("ADJ")),
+            ],
+            top: Some(3),
+        };
+        assert_eq!(
+            analysis.to_conllu(&["dogs", "will", "bark"], &mapping()),
+            "# text = dogs will bark
+1\tdogs\tdog\tNOUN\tN\tCase=nom|Number=Sing\t3\tnsubj\t_\t_
+2\twill\t_\tAUX\tAUX\tTense=Fut\t3\taux\t_\t_
+3\tbark\tbark\tVERB\tV\tTense=Fut\t0\troot\t_\t_
+4\t_\tloudly\tX\tADV\t_\t3\tdep\t_\t_
+
+"
+        );
+    }
+
+    #[test]
+    fn the_first_word_without_a_head_is_the_root_without_a_top_word() {
+        let analysis = SlotAnalysis {
+            words: vec![
+                word(1, "N", Some("dog"), &[], None, None),
+                word(2, "N", Some("cat"), &[], None, None),
+                word(3, "N", Some("mouse"), &[], Some(2), Some("SUBJ")),
+            ],
+            top: None,
+        };
+        let conllu = analysis.to_conllu(&[], &mapping());
+        let columns = conllu
+            .lines()
+            .filter(|line| !line.is_empty())
+            .map(|line| {
+                line.split('\t')
+                    .skip(6)
+                    .take(2)
+                    .collect::<Vec<_>>()
+                    .join(" ")
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(columns, ["0 root", "1 dep", "2 nsubj"]);
+    }
+}
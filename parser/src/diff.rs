@@ -0,0 +1,354 @@
+use crate::avm::*;
+use crate::chart::*;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fmt;
+
+/// A change of an AVM.
+/// Paths are sequences of attributes, atomic values are compared at the ends of paths
+/// (unvalued features having the value `[ ]`).
+#[derive(Debug, PartialEq, Eq)]
+pub enum AvmChange {
+    Added(Vec<String>, String),
+    Removed(Vec<String>, String),
+    Changed(Vec<String>, String, String),
+    ReentrancyAdded(Vec<String>, Vec<String>),
+    ReentrancyRemoved(Vec<String>, Vec<String>),
+}
+
+impl fmt::Display for AvmChange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AvmChange::Added(path, value) => write!(f, "+ {} = {}", path_string(path), value),
+            AvmChange::Removed(path, value) => write!(f, "- {} = {}", path_string(path), value),
+            AvmChange::Changed(path, old, new) => {
+                write!(f, "~ {}: {} -> {}", path_string(path), old, new)
+            }
+            AvmChange::ReentrancyAdded(p1, p2) => {
+                write!(f, "+ {} == {}", path_string(p1), path_string(p2))
+            }
+            AvmChange::ReentrancyRemoved(p1, p2) => {
+                write!(f, "- {} == {}", path_string(p1), path_string(p2))
+            }
+        }
+    }
+}
+
+fn path_string(path: &[String]) -> String {
+    if path.is_empty() {
+        "(root)".to_owned()
+    } else {
+        path.join(" ")
+    }
+}
+
+/// A path-wise difference between two AVMs.
+pub struct AvmDiff {
+    pub changes: Vec<AvmChange>,
+}
+
+impl AvmDiff {
+    /// Compares two AVMs.
+    pub fn new(old: &Avm, new: &Avm) -> Self {
+        let old = AvmPaths::new(old);
+        let new = AvmPaths::new(new);
+        let mut changes = Vec::new();
+        for (path, value) in &old.values {
+            match new.values.get(path) {
+                None => changes.push(AvmChange::Removed(path.to_owned(), value.to_owned())),
+                Some(new_value) if new_value != value => changes.push(AvmChange::Changed(
+                    path.to_owned(),
+                    value.to_owned(),
+                    new_value.to_owned(),
+                )),
+                _ => {}
+            }
+        }
+        for (path, value) in &new.values {
+            if !old.values.contains_key(path) {
+                changes.push(AvmChange::Added(path.to_owned(), value.to_owned()));
+            }
+        }
+        for (p1, p2) in old.reentrancies.difference(&new.reentrancies) {
+            changes.push(AvmChange::ReentrancyRemoved(p1.to_owned(), p2.to_owned()));
+        }
+        for (p1, p2) in new.reentrancies.difference(&old.reentrancies) {
+            changes.push(AvmChange::ReentrancyAdded(p1.to_owned(), p2.to_owned()));
+        }
+        Self { changes: changes }
+    }
+    pub fn is_empty(&self) -> bool {
+        self.changes.is_empty()
+    }
+}
+
+impl fmt::Display for AvmDiff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for change in &self.changes {
+            writeln!(f, "{}", change)?;
+        }
+        Ok(())
+    }
+}
+
+// The values and reentrancies of an AVM indexed by paths.
+struct AvmPaths {
+    values: BTreeMap<Vec<String>, String>,
+    reentrancies: BTreeSet<(Vec<String>, Vec<String>)>,
+}
+
+impl AvmPaths {
+    fn new(avm: &Avm) -> Self {
+        let mut values = BTreeMap::new();
+        let mut tagged = HashMap::new();
+        Self::collect(avm, &mut Vec::new(), &mut values, &mut tagged);
+        let mut reentrancies = BTreeSet::new();
+        for paths in tagged.values_mut() {
+            paths.sort();
+            for (i, p1) in paths.iter().enumerate() {
+                for p2 in &paths[i + 1..] {
+                    reentrancies.insert((p1.to_owned(), p2.to_owned()));
+                }
+            }
+        }
+        Self {
+            values: values,
+            reentrancies: reentrancies,
+        }
+    }
+    fn collect(
+        avm: &Avm,
+        path: &mut Vec<String>,
+        values: &mut BTreeMap<Vec<String>, String>,
+        tagged: &mut HashMap<usize, Vec<Vec<String>>>,
+    ) {
+        if let Some(tag) = avm.tag() {
+            tagged.entry(tag).or_default().push(path.to_owned());
+        }
+        if avm.features().next().is_none() {
+            values.insert(path.to_owned(), "[ ]".to_owned());
+        }
+        for (attr, value) in avm.features() {
+            path.push(attr.to_owned());
+            match value {
+                AvmView::Atom(value) => {
+                    values.insert(path.to_owned(), value.to_owned());
+                }
+                AvmView::Avm(value) => Self::collect(value, path, values, tagged),
+                AvmView::Tag(tag) => tagged.entry(tag).or_default().push(path.to_owned()),
+            }
+            path.pop();
+        }
+    }
+}
+
+/// A change of a syntax tree: the subtree at the given position has been replaced.
+/// Positions are sequences of labels with 1-based indices of children (e.g. `S/VP[2]`).
+#[derive(Debug, PartialEq, Eq)]
+pub struct TreeChange {
+    pub position: String,
+    pub old: String,
+    pub new: String,
+}
+
+impl fmt::Display for TreeChange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "~ {}: {} -> {}", self.position, self.old, self.new)
+    }
+}
+
+/// A difference between two parse results, i.e. between their syntax trees and f-structures.
+/// The f-structures of the theories are compared pairwise.
+/// Subtrees created by different rules are changed even if their labels are the same.
+pub struct ParseDiff {
+    pub tree: Vec<TreeChange>,
+    pub avms: Vec<AvmDiff>,
+    pub old_theories: usize,
+    pub new_theories: usize,
+}
+
+impl ParseDiff {
+    /// Compares two edges.
+    pub fn new(old: &Edge, new: &Edge) -> Self {
+        let mut tree = Vec::new();
+        Self::diff_trees(old, new, &old.label, &mut tree);
+        let avms = old
+            .theories
+            .iter()
+            .zip(&new.theories)
+            .map(|(t1, t2)| {
+                AvmDiff::new(
+                    &Avm::from_theory(t1, &old.logvar),
+                    &Avm::from_theory(t2, &new.logvar),
+                )
+            })
+            .collect();
+        Self {
+            tree: tree,
+            avms: avms,
+            old_theories: old.theories.len(),
+            new_theories: new.theories.len(),
+        }
+    }
+    fn diff_trees(old: &Edge, new: &Edge, position: &str, changes: &mut Vec<TreeChange>) {
+        if old.label != new.label
+            || old.rule != new.rule
+            || old.children.len() != new.children.len()
+        {
+            changes.push(TreeChange {
+                position: position.to_owned(),
+                old: old.tree_with_rules(),
+                new: new.tree_with_rules(),
+            });
+            return;
+        }
+        for (i, (c1, c2)) in old.children.iter().zip(&new.children).enumerate() {
+            let position = format!("{}/{}[{}]", position, c1.label, i + 1);
+            Self::diff_trees(c1, c2, &position, changes);
+        }
+    }
+    pub fn is_empty(&self) -> bool {
+        self.tree.is_empty()
+            && self.avms.iter().all(|diff| diff.is_empty())
+            && self.old_theories == self.new_theories
+    }
+}
+
+impl fmt::Display for ParseDiff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if !self.tree.is_empty() {
+            writeln!(f, "tree:")?;
+            for change in &self.tree {
+                writeln!(f, "  {}", change)?;
+            }
+        }
+        for (i, diff) in self.avms.iter().enumerate() {
+            if !diff.is_empty() {
+                writeln!(f, "f-structure {}:", i + 1)?;
+                for change in &diff.changes {
+                    writeln!(f, "  {}", change)?;
+                }
+            }
+        }
+        if self.old_theories != self.new_theories {
+            writeln!(
+                f,
+                "theories: {} -> {}",
+                self.old_theories, self.new_theories
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::grammar::*;
+    use crate::rewr::*;
+    use std::rc::Rc;
+
+    fn path(attrs: &[&str]) -> Vec<String> {
+        attrs.iter().map(|attr| attr.to_string()).collect()
+    }
+
+    #[test]
+    fn avm_diffs_show_changed_values_and_reentrancies() {
+        let mut old = Avm::new();
+        old.set_atom(&["PRED"], "see");
+        old.set_atom(&["TENSE"], "past");
+        let mut subj = Avm::new();
+        subj.set_tag(Some(1));
+        subj.set_atom(&["PRED"], "dog");
+        old.set_avm(&["SUBJ"], subj);
+        old.set_ref(&["TOPIC"], 1);
+        let mut new = Avm::new();
+        new.set_atom(&["PRED"], "saw");
+        new.set_atom(&["SUBJ", "PRED"], "dog");
+        new.set_atom(&["TOPIC", "PRED"], "dog");
+        new.set_avm(&["ADJ"], Avm::new());
+        let diff = AvmDiff::new(&old, &new);
+        assert_eq!(
+            diff.changes,
+            [
+                AvmChange::Changed(path(&["PRED"]), "see".to_owned(), "saw".to_owned()),
+                AvmChange::Removed(path(&["TENSE"]), "past".to_owned()),
+                AvmChange::Added(path(&["ADJ"]), "[ ]".to_owned()),
+                AvmChange::Added(path(&["TOPIC", "PRED"]), "dog".to_owned()),
+                AvmChange::ReentrancyRemoved(path(&["SUBJ"]), path(&["TOPIC"])),
+            ]
+        );
+        assert_eq!(
+            diff.to_string(),
+            "~ PRED: see -> saw\n- TENSE = past\n+ ADJ = [ ]\n+ TOPIC PRED = dog\n- SUBJ == TOPIC\n"
+        );
+        let diff = AvmDiff::new(&new, &old);
+        assert!(diff.changes.contains(&AvmChange::ReentrancyAdded(
+            path(&["SUBJ"]),
+            path(&["TOPIC"])
+        )));
+        assert!(AvmDiff::new(&old, &old).is_empty());
+    }
+
+    // Equates the given attribute of `*` (or `*` itself) with a symbol.
+    fn eq(attr: &str, symbol: &str) -> Constraint {
+        let t1 = if attr == "*" {
+            Term::new("*", &[])
+        } else {
+            Term::new(attr, &["*"])
+        };
+        Constraint::Equal(t1, Term::new(symbol, &[]))
+    }
+
+    // Parses "dogs chase cats" with the object equated with the given attribute of the verb phrase,
+    // preceded by the given number of unused rules.
+    fn parse(obj: &str, unused_rules: usize) -> Rc<Edge> {
+        let mut g = Grammar::new();
+        g.add_rule(Rule::new(
+            "S",
+            &["N", "VP"],
+            vec![vec![vec![eq("SUBJ", ".")]], vec![vec![eq("*", ".")]]],
+        ));
+        for _ in 0..unused_rules {
+            g.add_rule(Rule::new(
+                "VP",
+                &["AUX", "V"],
+                vec![vec![vec![eq("*", ".")]], vec![vec![eq("*", ".")]]],
+            ));
+        }
+        g.add_rule(Rule::new(
+            "VP",
+            &["V", "N"],
+            vec![vec![vec![eq("*", ".")]], vec![vec![eq(obj, ".")]]],
+        ));
+        let tokens = [("N", "@dog"), ("V", "@chase"), ("N", "@cat")]
+            .iter()
+            .map(|(label, pred)| vec![Reading::new(label, vec![vec![eq("PRED", pred)]])])
+            .collect::<Vec<_>>();
+        let result = g.parse_sentence(&tokens, "S");
+        assert_eq!(result.edges.len(), 1);
+        result.edges[0].clone()
+    }
+
+    #[test]
+    fn parse_diffs_show_subtrees_of_other_rules() {
+        let diff = ParseDiff::new(&parse("OBJ", 0), &parse("OBJ2", 1));
+        assert_eq!(
+            diff.tree,
+            [TreeChange {
+                position: "S/VP[2]".to_owned(),
+                old: "VP#1(V,N)".to_owned(),
+                new: "VP#2(V,N)".to_owned(),
+            }]
+        );
+        assert_eq!(
+            diff.to_string(),
+            "tree:
+  ~ S/VP[2]: VP#1(V,N) -> VP#2(V,N)
+f-structure 1:
+  - OBJ PRED = cat
+  + OBJ2 PRED = cat
+"
+        );
+        assert!(ParseDiff::new(&parse("OBJ", 0), &parse("OBJ", 0)).is_empty());
+    }
+}
@@ -1,5 +1,8 @@
+use crate::avm::*;
 use crate::chart::*;
+use crate::pruning::*;
 use crate::rewr::*;
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
 use std::fmt;
 use std::rc::Rc;
 
@@ -11,6 +14,69 @@ pub struct RuleItem {
     constraints: Vec<Vec<Constraint>>,
 }
 
+impl RuleItem {
+    /// Creates a rule item from a symbol followed by `?` (skippable), `+` (repeatable) or `*` (both)
+    /// and alternative sets of constraints, in which `*` stands for the mother and `.` for the daughter.
+    pub fn new(symbol: &str, constraints: Vec<Vec<Constraint>>) -> Self {
+        let mut skippable = false;
+        let mut repeatable = false;
+        let mut chars = symbol.chars();
+        match chars.next_back() {
+            Some('*') => {
+                skippable = true;
+                repeatable = true;
+            }
+            Some('+') => repeatable = true,
+            Some('?') => skippable = true,
+            _ => chars = symbol.chars(),
+        }
+        Self {
+            symbol: chars.as_str().to_owned(),
+            skippable: skippable,
+            repeatable: repeatable,
+            constraints: constraints,
+        }
+    }
+    /// Extends each theory of the mother with each theory of a daughter matching the item and each set of the item's constraints,
+    /// dropping inconsistent combinations.
+    pub fn apply(
+        &self,
+        theories: &[RewritingSystem],
+        logvar: &str,
+        edge: &Edge,
+    ) -> Vec<RewritingSystem> {
+        let mut new_theories = Vec::new();
+        for rs in theories {
+            'eloop: for t in &edge.theories {
+                let mut rs = rs.clone();
+                for r in &t.rules {
+                    if !rs.add_rule(r.clone()) {
+                        continue 'eloop;
+                    }
+                }
+                new_theories.push(rs);
+            }
+        }
+        let mut new_theories2 = Vec::new();
+        for rs in &new_theories {
+            'cloop: for c in &self.constraints {
+                let mut rs = rs.clone();
+                for c in c {
+                    let c = c.clone_with_subst(vec![
+                        ("*".to_owned(), logvar.to_owned()),
+                        (".".to_owned(), edge.logvar.to_owned()),
+                    ]);
+                    if !rs.add_rule(c.rule()) {
+                        continue 'cloop;
+                    }
+                }
+                new_theories2.push(rs);
+            }
+        }
+        new_theories2
+    }
+}
+
 impl fmt::Debug for RuleItem {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(
@@ -65,48 +131,37 @@ impl fmt::Debug for Constraint {
 pub struct Rule {
     lhs: String,
     rhs: Vec<Rc<RuleItem>>,
+    // The ordering constraints of a discontinuous rule (`None` for a rule with adjacent daughters).
+    precedence: Option<Vec<(usize, usize)>>,
+    weight: f64,
 }
 
 impl Rule {
     pub fn new(lhs: &str, rhs_ref: &[&str], constraints: Vec<Vec<Vec<Constraint>>>) -> Self {
         let mut rhs = Vec::with_capacity(rhs_ref.len());
         for (i, &s) in rhs_ref.iter().enumerate() {
-            let mut skippable = false;
-            let mut repeatable = false;
-            let s = if let Some(c) = s.chars().last() {
-                let mut chars = s.chars();
-                match c {
-                    '*' => {
-                        skippable = true;
-                        repeatable = true;
-                        chars.next_back();
-                    }
-                    '+' => {
-                        repeatable = true;
-                        chars.next_back();
-                    }
-                    '?' => {
-                        skippable = true;
-                        chars.next_back();
-                    }
-                    _ => {}
-                }
-                chars.as_str().to_owned()
-            } else {
-                s.to_owned()
-            };
-            rhs.push(Rc::new(RuleItem {
-                symbol: s,
-                skippable: skippable,
-                repeatable: repeatable,
-                constraints: constraints.get(i).unwrap().to_owned(),
-            }));
+            rhs.push(Rc::new(RuleItem::new(
+                s,
+                constraints.get(i).unwrap().to_owned(),
+            )));
         }
         return Rule {
             lhs: lhs.to_owned(),
             rhs: rhs,
+            precedence: None,
+            weight: 0.0,
         };
     }
+    /// Sets the weight (log-probability) of the rule, which is added to the scores of the edges it creates.
+    pub fn set_weight(&mut self, weight: f64) {
+        self.weight = weight;
+    }
+    /// Makes the rule discontinuous: its daughters need not be adjacent and may occur in any order,
+    /// except that for each pair `(i, j)` the daughters of the `i`-th item must precede those of the `j`-th item.
+    /// The daughters of a repeatable item occur in the order of the input.
+    pub fn set_discontinuous(&mut self, precedence: Vec<(usize, usize)>) {
+        self.precedence = Some(precedence);
+    }
 }
 
 impl fmt::Debug for Rule {
@@ -120,12 +175,131 @@ impl fmt::Debug for Rule {
                 .map(|item| format!("{:?}", item))
                 .collect::<Vec<_>>()
                 .join(" ")
-        )
+        )?;
+        if let Some(precedence) = &self.precedence {
+            write!(
+                f,
+                " {{{}}}",
+                precedence
+                    .iter()
+                    .map(|(i, j)| format!("{} < {}", i, j))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// A reading of an input token: a lexical category with alternative sets of constraints.
+pub struct Reading {
+    pub label: String,
+    pub constraints: Vec<Vec<Constraint>>,
+    /// The weight (log-probability) of the reading.
+    pub weight: f64,
+}
+
+impl Reading {
+    pub fn new(label: &str, constraints: Vec<Vec<Constraint>>) -> Self {
+        Self {
+            label: label.to_owned(),
+            constraints: constraints,
+            weight: 0.0,
+        }
+    }
+    /// Creates a lexical edge for the reading.
+    pub fn to_edge(&self, start: i32, end: i32, logvar: &str) -> Edge {
+        let mut edge = Edge::new(start, end, &self.label, logvar, self.constraints.to_owned());
+        edge.weight = self.weight;
+        edge
+    }
+}
+
+/// A complete analysis of the input.
+pub struct Analysis {
+    pub edge: Rc<Edge>,
+    /// The AVMs of the edge, one for each of its theories.
+    pub avms: Vec<Avm>,
+    /// The score of the tree (0 without weights).
+    pub score: f64,
+}
+
+impl Analysis {
+    /// Creates the analysis of a tree (an edge without packed alternatives).
+    pub fn new(tree: Rc<Edge>) -> Self {
+        let avms = tree
+            .theories
+            .iter()
+            .map(|t| Avm::from_theory(t, &tree.logvar))
+            .collect();
+        Self {
+            score: tree.score(),
+            edge: tree,
+            avms: avms,
+        }
+    }
+}
+
+/// The result of parsing a sentence.
+pub struct ParseResult {
+    /// The chart after parsing (for inspection).
+    pub chart: Chart,
+    /// The edges spanning the whole input with the start symbol as their label.
+    /// They may hold several trees if the chart packs edges, which are only unpacked when analyses are requested.
+    pub edges: Vec<Rc<Edge>>,
+    /// The fragments covering the input if there are no complete analyses (and the grammar is in fragment mode).
+    pub fragments: Option<Fragments>,
+    /// What has been pruned during parsing.
+    pub pruned: PruningReport,
+}
+
+/// A fragmentary analysis: a sequence of edges covering the input (except for positions no edge covers).
+pub struct Fragments {
+    pub edges: Vec<Rc<Edge>>,
+    /// The list of the fragments' f-structures as the value of `FRAGMENTS` (with `FIRST` and `REST` attributes).
+    pub avm: Avm,
+}
+
+impl ParseResult {
+    /// Returns the number of complete analyses without unpacking them.
+    /// Trees whose packed alternatives turn out to be inconsistent when unpacked are counted too.
+    pub fn count(&self) -> usize {
+        self.edges.iter().map(|edge| edge.tree_count()).sum()
+    }
+    /// Returns the complete analyses, unpacking the trees of the complete edges.
+    pub fn analyses(&self) -> Vec<Analysis> {
+        self.edges
+            .iter()
+            .flat_map(|edge| edge.trees())
+            .map(Analysis::new)
+            .collect()
+    }
+    /// Returns the analysis with the best score (the first one among analyses with the same score).
+    pub fn best(&self) -> Option<Analysis> {
+        self.n_best(1).pop()
+    }
+    /// Returns the `n` best analyses ordered by descending score.
+    /// They are extracted from the packed complete edges without unpacking all analyses.
+    pub fn n_best(&self, n: usize) -> Vec<Analysis> {
+        Edge::n_best_of(&self.edges, n)
+            .into_iter()
+            .map(Analysis::new)
+            .collect()
     }
 }
 
 struct ParseContext {
     log_id: i32,
+    prediction: Option<Prediction>,
+    pruned: PruningReport,
+}
+
+// The state of the top-down filter: the symbols predicted at each position of the chart.
+struct Prediction {
+    start_symbol: String,
+    start: i32,
+    left_corners: BTreeMap<String, BTreeSet<String>>,
+    predicted: BTreeMap<i32, BTreeSet<String>>,
 }
 
 impl ParseContext {
@@ -138,92 +312,1007 @@ impl ParseContext {
 /// A context-free grammar with constraints as rule annotations.
 pub struct Grammar {
     rules: Vec<Rule>,
+    ordering: Rc<dyn TermOrdering>,
+    cycle_policy: CyclePolicy,
+    start_symbol: Option<String>,
+    fragments: bool,
+    pruning: Option<Pruning>,
+    packing: bool,
 }
 
 impl Grammar {
     pub fn new() -> Self {
-        Self { rules: Vec::new() }
+        Self {
+            rules: Vec::new(),
+            ordering: Rc::new(LengthLexOrdering),
+            cycle_policy: CyclePolicy::Allow,
+            start_symbol: None,
+            fragments: false,
+            pruning: None,
+            packing: false,
+        }
     }
     pub fn add_rule(&mut self, rule: Rule) {
         self.rules.push(rule);
     }
-    /// Extends the given chart by applying the grammar's rules.
-    pub fn parse(&self, chart: &mut Chart) {
-        let mut ctx = ParseContext { log_id: 0 };
-        self.parse_level(chart, 0, &mut ctx);
+    /// Returns the rule with the given index (in the order the rules have been added).
+    pub fn rule(&self, index: usize) -> Option<&Rule> {
+        self.rules.get(index)
+    }
+    /// Sets the ordering used to orient equations in the theories built during parsing.
+    pub fn set_ordering(&mut self, ordering: Rc<dyn TermOrdering>) {
+        self.ordering = ordering;
+    }
+    /// Sets how equations creating cyclic f-structures are treated during parsing.
+    pub fn set_cycle_policy(&mut self, policy: CyclePolicy) {
+        self.cycle_policy = policy;
+    }
+    /// Enables (or disables with `None`) the top-down filter.
+    /// An edge is then only created if its label can be predicted from the given start symbol and the left context.
+    pub fn set_prediction(&mut self, start_symbol: Option<&str>) {
+        self.start_symbol = start_symbol.map(|s| s.to_owned());
+    }
+    /// Sets the limits on the edges and theories kept during parsing (`None` keeps everything).
+    pub fn set_pruning(&mut self, pruning: Option<Pruning>) {
+        self.pruning = pruning;
+    }
+    /// Enables or disables local ambiguity packing in the charts built by `parse_sentence` and `parse_lattice`.
+    pub fn set_packing(&mut self, packing: bool) {
+        self.packing = packing;
     }
-    fn parse_level(&self, chart: &mut Chart, level: i32, ctx: &mut ParseContext) {
-        let mut new_edges = Vec::new();
+    /// Checks whether the charts built by `parse_sentence` and `parse_lattice` pack edges.
+    pub fn packing(&self) -> bool {
+        self.packing
+    }
+    /// Enables or disables fragment mode, in which an input without complete analyses is covered by fragments.
+    pub fn set_fragments(&mut self, fragments: bool) {
+        self.fragments = fragments;
+    }
+    /// Returns the reflexive and transitive left-corner relation of the grammar's symbols.
+    /// Skippable items are passed over, so the item following them is a left corner too.
+    /// Any item of a discontinuous rule may be its left corner.
+    pub fn left_corners(&self) -> BTreeMap<String, BTreeSet<String>> {
+        let mut left_corners = BTreeMap::new();
         for rule in &self.rules {
-            chart.find_paths(&rule.rhs, &mut |edges, items| {
-                let mut max_level = 0;
-                for edge in edges {
-                    if edge.level > max_level {
-                        max_level = edge.level;
-                    }
+            let corners = left_corners
+                .entry(rule.lhs.to_owned())
+                .or_insert_with(BTreeSet::new);
+            corners.insert(rule.lhs.to_owned());
+            for item in &rule.rhs {
+                corners.insert(item.symbol.to_owned());
+                if !item.skippable && rule.precedence.is_none() {
+                    break;
                 }
-                if level == max_level {
-                    let mut theories = Vec::new();
-                    theories.push(RewritingSystem::new());
-                    let new_id = format!("g{}", ctx.unique_id());
-                    for (i, edge) in edges.iter().enumerate() {
-                        let item = items.get(i).unwrap();
-                        let mut new_theories = Vec::new();
-                        for rs in &theories {
-                            'eloop: for t in &edge.theories {
-                                let mut rs = rs.clone();
-                                for r in &t.rules {
-                                    if !rs.add_rule(r.clone()) {
-                                        continue 'eloop;
-                                    }
-                                }
-                                new_theories.push(rs);
+            }
+        }
+        loop {
+            let mut new_corners = Vec::new();
+            for (symbol, corners) in &left_corners {
+                for corner in corners {
+                    if let Some(corners2) = left_corners.get(corner) {
+                        for corner2 in corners2 {
+                            if !corners.contains(corner2) {
+                                new_corners.push((symbol.to_owned(), corner2.to_owned()));
                             }
                         }
-                        let mut new_theories2 = Vec::new();
-                        for rs in &new_theories {
-                            'cloop: for c in &item.constraints {
-                                let mut rs = rs.clone();
-                                for c in c {
-                                    let c = c.clone_with_subst(vec![
-                                        ("*".to_owned(), new_id.to_owned()),
-                                        (".".to_owned(), edge.logvar.to_owned()),
-                                    ]);
-                                    if !rs.add_rule(c.rule()) {
-                                        continue 'cloop;
-                                    }
-                                }
-                                new_theories2.push(rs);
-                            }
+                    }
+                }
+            }
+            if new_corners.is_empty() {
+                break;
+            }
+            for (symbol, corner) in new_corners {
+                left_corners.get_mut(&symbol).unwrap().insert(corner);
+            }
+        }
+        left_corners
+    }
+    /// Checks whether the symbol is predicted at the given position.
+    fn is_predicted(
+        &self,
+        chart: &Chart,
+        symbol: &str,
+        pos: i32,
+        prediction: &mut Prediction,
+    ) -> bool {
+        if !prediction.predicted.contains_key(&pos) {
+            let predicted = self.predict(chart, pos, prediction);
+            prediction.predicted.insert(pos, predicted);
+        }
+        prediction.predicted[&pos].contains(symbol)
+    }
+    /// Computes the symbols predicted at the given position.
+    /// All edges ending at the position must already be in the chart.
+    /// The items of discontinuous rules are predicted everywhere.
+    fn predict(&self, chart: &Chart, pos: i32, prediction: &mut Prediction) -> BTreeSet<String> {
+        let mut expected = BTreeSet::new();
+        if pos == prediction.start {
+            expected.insert(prediction.start_symbol.to_owned());
+        }
+        for rule in &self.rules {
+            if rule.precedence.is_some() {
+                expected.extend(rule.rhs.iter().map(|item| item.symbol.to_owned()));
+                continue;
+            }
+            // The positions where a prefix of the rule starts and the index of its last item.
+            let mut prefixes = BTreeSet::new();
+            for i in 1..=rule.rhs.len() {
+                chart.find_paths_ending_at(pos, &rule.rhs[..i], &mut |edges, items| {
+                    let last = items.last().unwrap();
+                    let index = rule.rhs.iter().position(|item| Rc::ptr_eq(item, last));
+                    prefixes.insert((edges.first().unwrap().start, index.unwrap()));
+                });
+            }
+            for (start, index) in prefixes {
+                if !self.is_predicted(chart, &rule.lhs, start, prediction) {
+                    continue;
+                }
+                if rule.rhs[index].repeatable {
+                    expected.insert(rule.rhs[index].symbol.to_owned());
+                }
+                for item in &rule.rhs[index + 1..] {
+                    expected.insert(item.symbol.to_owned());
+                    if !item.skippable {
+                        break;
+                    }
+                }
+            }
+        }
+        let mut predicted = BTreeSet::new();
+        for symbol in expected {
+            if let Some(corners) = prediction.left_corners.get(&symbol) {
+                predicted.extend(corners.iter().cloned());
+            }
+            predicted.insert(symbol);
+        }
+        predicted
+    }
+    /// Creates an empty theory configured by the grammar.
+    fn new_theory(&self) -> RewritingSystem {
+        let mut rs = RewritingSystem::with_ordering(self.ordering.clone());
+        rs.set_cycle_policy(self.cycle_policy);
+        rs
+    }
+    /// Rebuilds the theories of a lexical edge with the grammar's ordering and cycle policy.
+    /// Theories rejected by the cycle policy are dropped, as is the edge if none remains.
+    /// Alternatives packed into a lexical edge are returned as separate edges, to be packed again when parsing.
+    fn configure_lexical_edge(&self, edge: Rc<Edge>) -> Vec<Rc<Edge>> {
+        if !edge.children.is_empty() {
+            return vec![edge];
+        }
+        let alternatives = edge.packed.borrow().clone();
+        [edge]
+            .into_iter()
+            .chain(alternatives)
+            .filter_map(|edge| self.configure_lexical_theories(&edge))
+            .collect()
+    }
+    /// Rebuilds the theories of a lexical edge (ignoring the alternatives packed into it).
+    fn configure_lexical_theories(&self, edge: &Edge) -> Option<Rc<Edge>> {
+        let mut theories = Vec::new();
+        'tloop: for t in &edge.theories {
+            let mut rs = self.new_theory();
+            for r in &t.rules {
+                if !rs.add_rule(r.clone()) {
+                    continue 'tloop;
+                }
+            }
+            theories.push(rs);
+        }
+        if theories.is_empty() {
+            return None;
+        }
+        let mut new_edge = Edge::new_with_children(
+            edge.start,
+            edge.end,
+            &edge.label,
+            &edge.logvar,
+            theories,
+            edge.level,
+            Vec::new(),
+        );
+        new_edge.weight = edge.weight;
+        Some(Rc::new(new_edge))
+    }
+    /// Extends the given chart by applying the grammar's rules.
+    /// The theories of lexical edges are rebuilt with the grammar's ordering and cycle policy first.
+    /// Edges are processed from an agenda, each edge being combined only with edges processed before it.
+    /// The agenda is ordered by end positions, so all edges ending at a position are known when edges starting there are predicted.
+    /// Edges packed into equivalent edges (if the chart packs them) aren't combined any further.
+    /// When pruning, the best edge ending at a position is taken from the agenda first and an edge taken
+    /// when its span is already full replaces the worst edge of the span if it is better (and is dropped otherwise).
+    pub fn parse(&self, chart: &mut Chart) -> PruningReport {
+        let edges = chart.all_edges();
+        let prediction = self.start_symbol.as_ref().map(|start_symbol| Prediction {
+            start_symbol: start_symbol.to_owned(),
+            start: edges.iter().map(|edge| edge.start).min().unwrap_or(0),
+            left_corners: self.left_corners(),
+            predicted: BTreeMap::new(),
+        });
+        let mut ctx = ParseContext {
+            log_id: 0,
+            prediction: prediction,
+            pruned: PruningReport::new(),
+        };
+        let pruning = self.pruning.as_ref();
+        let score = |edge: &Edge| pruning.map_or(0.0, |pruning| pruning.score(edge));
+        let mut agenda = BTreeMap::new();
+        for edge in edges
+            .into_iter()
+            .flat_map(|edge| self.configure_lexical_edge(edge))
+        {
+            agenda
+                .entry(edge.end)
+                .or_insert_with(VecDeque::new)
+                .push_back((score(&edge), edge));
+        }
+        chart.clear();
+        while let Some(mut entry) = agenda.first_entry() {
+            let mut index = 0;
+            if pruning.is_some_and(|pruning| pruning.scorer.is_some()) {
+                for (i, (score, _)) in entry.get().iter().enumerate() {
+                    if *score > entry.get()[index].0 {
+                        index = i;
+                    }
+                }
+            }
+            let (edge_score, edge) = entry.get_mut().remove(index).unwrap();
+            if entry.get().is_empty() {
+                entry.remove();
+            }
+            if let Some(max) = pruning.and_then(|pruning| pruning.max_edges_per_span) {
+                let span = chart.edges_between(edge.start, edge.end);
+                if span.len() >= max {
+                    let worst = span
+                        .iter()
+                        .map(|edge| (score(edge), edge))
+                        .min_by(|(s1, _), (s2, _)| s1.total_cmp(s2));
+                    match worst {
+                        Some((worst_score, worst)) if worst_score < edge_score => {
+                            chart.remove_edge(worst);
+                            ctx.pruned.evicted += 1;
                         }
-                        theories = new_theories2;
-                        if theories.is_empty() {
-                            break;
+                        _ => {
+                            ctx.pruned.edges_per_span += 1;
+                            continue;
+                        }
+                    }
+                }
+            }
+            if chart.add_edge(edge.clone()) != Ok(true) {
+                continue;
+            }
+            for (index, rule) in self.rules.iter().enumerate() {
+                let mut cb = |edges: &Vec<Rc<Edge>>, items: &Vec<Rc<RuleItem>>| {
+                    if let Some(prediction) = &mut ctx.prediction {
+                        let start = edges.iter().map(|edge| edge.start).min().unwrap();
+                        if !self.is_predicted(chart, &rule.lhs, start, prediction) {
+                            return;
                         }
                     }
-                    if !theories.is_empty() {
-                        for edge in edges {
-                            edge.used.set(true);
+                    if let Some(mut edge) = self.apply_rule(index, edges, items, &mut ctx) {
+                        if let Some(pruning) = pruning {
+                            ctx.pruned.theories += pruning.prune_theories(&mut edge);
                         }
-                        let edge = Edge::new_with_children(
-                            edges.first().unwrap().start,
-                            edges.last().unwrap().end,
-                            &rule.lhs,
-                            &new_id,
-                            theories,
-                            level + 1,
-                            edges.to_owned(),
-                        );
-                        new_edges.push(Rc::new(edge));
+                        agenda
+                            .entry(edge.end)
+                            .or_insert_with(VecDeque::new)
+                            .push_back((score(&edge), Rc::new(edge)));
                     }
+                };
+                match &rule.precedence {
+                    Some(precedence) => {
+                        chart.find_combinations_with(&edge, &rule.rhs, precedence, &mut cb)
+                    }
+                    None => chart.find_paths_with(&edge, &rule.rhs, &mut cb),
                 }
+            }
+        }
+        ctx.pruned
+    }
+    /// Parses a sentence given as the readings of its tokens.
+    /// The edge of the `i`-th token spans the positions `i` and `i + 1`, its logical variable being `w{i}`.
+    pub fn parse_sentence(&self, tokens: &[Vec<Reading>], start_symbol: &str) -> ParseResult {
+        let mut chart = Chart::new();
+        chart.set_packing(self.packing);
+        for (i, readings) in tokens.iter().enumerate() {
+            for reading in readings {
+                let edge = reading.to_edge(i as i32, i as i32 + 1, &format!("w{}", i));
+                chart.add_edge(Rc::new(edge)).unwrap();
+            }
+        }
+        self.parse_chart(chart, 0, tokens.len() as i32, start_symbol)
+    }
+    /// Parses the given chart, returning the edges covering the positions between the given ones without gaps
+    /// and with the start symbol as their label.
+    pub fn parse_chart(
+        &self,
+        mut chart: Chart,
+        start: i32,
+        end: i32,
+        start_symbol: &str,
+    ) -> ParseResult {
+        let pruned = self.parse(&mut chart);
+        let mut edges = Vec::new();
+        for edge in chart.all_edges() {
+            let complete = edge.start == start
+                && edge.end == end
+                && edge.label == start_symbol
+                && edge.coverage.is_contiguous();
+            if complete {
+                edges.push(edge);
+            }
+        }
+        let mut fragments = None;
+        if edges.is_empty() && self.fragments {
+            let edges = chart.fragments(start, end);
+            fragments = Some(Fragments {
+                avm: self.fragments_avm(&edges),
+                edges: edges,
             });
         }
-        if new_edges.len() > 0 {
-            for edge in &new_edges {
-                chart.add_edge(edge.clone());
+        ParseResult {
+            chart: chart,
+            edges: edges,
+            fragments: fragments,
+            pruned: pruned,
+        }
+    }
+    /// Returns the list of the f-structures of the given edges, using the first theory of each edge
+    /// consistent with the theories used for the edges before it.
+    /// The f-structure of an edge without such a theory is left empty.
+    fn fragments_avm(&self, edges: &[Rc<Edge>]) -> Avm {
+        let mut rs = self.new_theory();
+        let mut path = vec!["FRAGMENTS", "root"];
+        for edge in edges {
+            'tloop: for t in &edge.theories {
+                let mut new_rs = rs.clone();
+                for r in &t.rules {
+                    if !new_rs.add_rule(r.clone()) {
+                        continue 'tloop;
+                    }
+                }
+                rs = new_rs;
+                break;
+            }
+            rs.add_rule(RewriteRule::new(
+                Term::new("FIRST", &path),
+                Term::new(&edge.logvar, &[]),
+            ));
+            path.insert(0, "REST");
+        }
+        Avm::from_theory(&rs, "root")
+    }
+    /// Creates an edge spanning the given daughter edges (unless they overlap or their theories and constraints are inconsistent).
+    fn apply_rule(
+        &self,
+        index: usize,
+        edges: &[Rc<Edge>],
+        items: &[Rc<RuleItem>],
+        ctx: &mut ParseContext,
+    ) -> Option<Edge> {
+        let rule = &self.rules[index];
+        for (i, edge1) in edges.iter().enumerate() {
+            if edges[..i]
+                .iter()
+                .any(|edge2| !edge1.coverage.is_disjoint(&edge2.coverage))
+            {
+                return None;
+            }
+        }
+        let mut theories = Vec::new();
+        theories.push(self.new_theory());
+        let new_id = format!("g{}", ctx.unique_id());
+        for (i, edge) in edges.iter().enumerate() {
+            theories = items.get(i).unwrap().apply(&theories, &new_id, edge);
+            if theories.is_empty() {
+                return None;
+            }
+        }
+        let level = edges.iter().map(|edge| edge.level).max().unwrap();
+        let mut edge = Edge::new_with_children(
+            edges.iter().map(|edge| edge.start).min().unwrap(),
+            edges.iter().map(|edge| edge.end).max().unwrap(),
+            &rule.lhs,
+            &new_id,
+            theories,
+            level + 1,
+            edges.to_owned(),
+        );
+        edge.weight = rule.weight;
+        edge.rule = Some(index);
+        edge.items = items.to_owned();
+        Some(edge)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn term(s: &str) -> Term {
+        match s.split_once('(') {
+            Some((head, arg)) => Term {
+                head: Rc::from(head),
+                arg: Some(Rc::new(term(&arg[..arg.len() - 1]))),
+            },
+            None => Term::new(s, &[]),
+        }
+    }
+
+    fn eq(t1: &str, t2: &str) -> Constraint {
+        Constraint::Equal(term(t1), term(t2))
+    }
+
+    fn rule(lhs: &str, rhs: &[(&str, &str)]) -> Rule {
+        let symbols = rhs.iter().map(|(symbol, _)| *symbol).collect::<Vec<_>>();
+        let constraints = rhs
+            .iter()
+            .map(|(_, path)| vec![vec![eq(path, ".")]])
+            .collect();
+        Rule::new(lhs, &symbols, constraints)
+    }
+
+    // A grammar with skippable, repeatable and optional repeatable items.
+    // Adjuncts of verb phrases can be attached in two ways with the same f-structure.
+    fn grammar() -> Grammar {
+        let mut g = Grammar::new();
+        g.add_rule(rule("S", &[("NP", "SUBJ(*)"), ("VP", "*")]));
+        g.add_rule(rule("VP", &[("AUX?", "*"), ("V", "*"), ("NP?", "OBJ(*)")]));
+        g.add_rule(rule("VP", &[("VP", "*"), ("PP", "ADJ(*)")]));
+        g.add_rule(rule(
+            "VP",
+            &[
+                ("AUX?", "*"),
+                ("V", "*"),
+                ("NP", "OBJ(*)"),
+                ("PP", "ADJ(*)"),
+            ],
+        ));
+        g.add_rule(rule(
+            "NP",
+            &[("DET?", "SPEC(*)"), ("A*", "ADJ(*)"), ("N+", "*")],
+        ));
+        g.add_rule(rule("NP", &[("NP", "*"), ("PP", "ADJ(*)")]));
+        g.add_rule(rule("PP", &[("P", "*"), ("NP", "OBJ(*)")]));
+        g
+    }
+
+    // The readings of words with the given labels, each word contributing a feature of its own
+    // so that the daughters of repeatable items don't clash.
+    fn readings(words: &[(&str, &str)]) -> Vec<Vec<Reading>> {
+        words
+            .iter()
+            .map(|(labels, word)| {
+                labels
+                    .split('|')
+                    .map(|label| {
+                        let feature = format!("{}(*)", word.to_uppercase());
+                        Reading::new(label, vec![vec![eq(&feature, "@+")]])
+                    })
+                    .collect()
+            })
+            .collect()
+    }
+
+    fn chart(words: &[(&str, &str)]) -> Chart {
+        let mut chart = Chart::new();
+        for (i, readings) in readings(words).iter().enumerate() {
+            for reading in readings {
+                let logvar = format!("w{}", i);
+                chart
+                    .add_edge(Rc::new(reading.to_edge(i as i32, i as i32 + 1, &logvar)))
+                    .unwrap();
+            }
+        }
+        chart
+    }
+
+    const SENTENCE: &[(&str, &str)] = &[
+        ("DET", "the"),
+        ("A", "old"),
+        ("A", "big"),
+        ("N", "dog"),
+        ("AUX", "will"),
+        ("V|N", "chase"),
+        ("N", "cats"),
+        ("N", "mice"),
+        ("P", "in"),
+        ("N|V", "parks"),
+    ];
+
+    // Describes an edge independently of the names of the variables created during parsing.
+    fn describe(edge: &Edge) -> String {
+        let avms = edge
+            .theories
+            .iter()
+            .map(|t| Avm::from_theory(t, &edge.logvar).to_json())
+            .collect::<Vec<_>>();
+        format!(
+            "{}-{} {} {}",
+            edge.start,
+            edge.end,
+            edge.tree_with_rules(),
+            avms.join(" | ")
+        )
+    }
+
+    fn describe_all(edges: &[Rc<Edge>]) -> Vec<String> {
+        let mut descriptions = edges.iter().map(|e| describe(e)).collect::<Vec<_>>();
+        descriptions.sort();
+        descriptions
+    }
+
+    // The level-wise algorithm the agenda replaced: all rules are matched against the whole chart
+    // at each level, keeping the paths whose highest daughter is of the current level.
+    fn parse_level_wise(g: &Grammar, chart: &mut Chart) {
+        let edges = chart.all_edges();
+        chart.clear();
+        for edge in edges {
+            for edge in g.configure_lexical_edge(edge) {
+                chart.add_edge(edge).unwrap();
+            }
+        }
+        let mut ctx = ParseContext {
+            log_id: 0,
+            prediction: None,
+            pruned: PruningReport::new(),
+        };
+        let mut level = 0;
+        loop {
+            let mut new_edges = Vec::new();
+            for (index, rule) in g.rules.iter().enumerate() {
+                chart.find_paths(&rule.rhs, &mut |edges, items| {
+                    if edges.iter().map(|e| e.level).max() == Some(level) {
+                        new_edges.extend(g.apply_rule(index, edges, items, &mut ctx));
+                    }
+                });
+            }
+            if new_edges.is_empty() {
+                break;
+            }
+            for edge in new_edges {
+                chart.add_edge(Rc::new(edge)).unwrap();
+            }
+            level += 1;
+        }
+    }
+
+    #[test]
+    fn agenda_parsing_finds_the_edges_of_level_wise_parsing() {
+        let g = grammar();
+        for n in 1..=SENTENCE.len() {
+            let mut chart1 = chart(&SENTENCE[..n]);
+            g.parse(&mut chart1);
+            let mut chart2 = chart(&SENTENCE[..n]);
+            parse_level_wise(&g, &mut chart2);
+            assert_eq!(
+                describe_all(&chart1.all_edges()),
+                describe_all(&chart2.all_edges())
+            );
+        }
+        let mut chart = chart(SENTENCE);
+        g.parse(&mut chart);
+        let complete = chart
+            .edges_between(0, SENTENCE.len() as i32)
+            .into_iter()
+            .filter(|e| e.label == "S")
+            .count();
+        assert_eq!(complete, 3);
+    }
+
+    fn complete_edges(chart: &Chart, label: &str, end: usize) -> Vec<String> {
+        let edges = chart.edges_between(0, end as i32);
+        describe_all(
+            &edges
+                .into_iter()
+                .filter(|e| e.label == label)
+                .collect::<Vec<_>>(),
+        )
+    }
+
+    #[test]
+    fn prediction_only_drops_edges_outside_complete_analyses() {
+        let mut g = grammar();
+        let mut chart1 = chart(SENTENCE);
+        g.parse(&mut chart1);
+        g.set_prediction(Some("S"));
+        let mut chart2 = chart(SENTENCE);
+        g.parse(&mut chart2);
+        let all = describe_all(&chart1.all_edges());
+        let predicted = describe_all(&chart2.all_edges());
+        assert!(predicted.iter().all(|e| all.contains(e)));
+        assert!(predicted.len() < all.len());
+        assert_eq!(
+            complete_edges(&chart1, "S", SENTENCE.len()),
+            complete_edges(&chart2, "S", SENTENCE.len())
+        );
+    }
+
+    #[test]
+    fn packing_yields_the_trees_of_unpacked_parsing() {
+        let g = grammar();
+        let mut chart1 = chart(SENTENCE);
+        g.parse(&mut chart1);
+        let mut chart2 = chart(SENTENCE);
+        chart2.set_packing(true);
+        g.parse(&mut chart2);
+        assert!(chart2.all_edges().len() < chart1.all_edges().len());
+        let complete = chart2
+            .edges_between(0, SENTENCE.len() as i32)
+            .into_iter()
+            .filter(|e| e.label == "S")
+            .collect::<Vec<_>>();
+        assert_eq!(complete.len(), 2);
+        assert_eq!(complete.iter().map(|e| e.tree_count()).sum::<usize>(), 3);
+        let trees = complete.iter().flat_map(|e| e.trees()).collect::<Vec<_>>();
+        assert_eq!(
+            describe_all(&trees),
+            complete_edges(&chart1, "S", SENTENCE.len())
+        );
+    }
+
+    #[test]
+    fn packed_alternatives_differing_outside_their_f_structures_are_unpacked() {
+        let mut g = Grammar::new();
+        g.add_rule(rule("X", &[("Y", "A(*)"), ("Z", "B(*)")]));
+        g.add_rule(rule("S", &[("X", "*")]));
+        // The readings of the first word only differ in a variable outside their f-structures.
+        let y = |value: &str| Reading::new("Y", vec![vec![eq("PRED(*)", "@y"), eq("Q(v)", value)]]);
+        let tokens = vec![
+            vec![y("@1"), y("@2")],
+            vec![Reading::new("Z", vec![vec![eq("PRED(*)", "@z")]])],
+        ];
+        let values = |result: &ParseResult| {
+            let mut values = result
+                .analyses()
+                .iter()
+                .flat_map(|a| a.edge.theories.iter().map(|t| t.norm(&term("Q(v)"))))
+                .map(|t| format!("{:?}", t))
+                .collect::<Vec<_>>();
+            values.sort();
+            values
+        };
+        let result1 = g.parse_sentence(&tokens, "S");
+        g.set_packing(true);
+        let result2 = g.parse_sentence(&tokens, "S");
+        assert_eq!(result2.chart.all_edges().len(), 4);
+        assert_eq!(result1.count(), 2);
+        assert_eq!(result2.count(), 2);
+        assert_eq!(values(&result1), ["@1", "@2"]);
+        assert_eq!(values(&result2), ["@1", "@2"]);
+    }
+
+    #[test]
+    fn edges_are_not_packed_into_their_descendants() {
+        let mut g = Grammar::new();
+        g.add_rule(rule("NP", &[("NP", "*"), ("PP?", "ADJ(*)")]));
+        g.set_packing(true);
+        let result = g.parse_sentence(&readings(&[("NP", "dogs")]), "NP");
+        assert_eq!(result.chart.all_edges().len(), 1);
+        assert_eq!(result.count(), 1);
+        assert_eq!(result.analyses().len(), 1);
+        assert_eq!(result.n_best(2).len(), 1);
+    }
+
+    #[test]
+    fn parse_results_unpack_packed_analyses_on_demand() {
+        let mut g = grammar();
+        let result1 = g.parse_sentence(&readings(SENTENCE), "S");
+        g.set_packing(true);
+        let result2 = g.parse_sentence(&readings(SENTENCE), "S");
+        assert_eq!(result1.edges.len(), 3);
+        assert_eq!(result2.edges.len(), 2);
+        assert_eq!(result1.count(), 3);
+        assert_eq!(result2.count(), 3);
+        let describe_analyses = |result: &ParseResult| {
+            let trees = result
+                .analyses()
+                .into_iter()
+                .map(|a| a.edge)
+                .collect::<Vec<_>>();
+            describe_all(&trees)
+        };
+        assert_eq!(describe_analyses(&result1), describe_analyses(&result2));
+    }
+
+    // A grammar in which the auxiliary may be detached from the verb phrase in discontinuous mode.
+    fn auxiliary_grammar(discontinuous: bool) -> Grammar {
+        let mut g = Grammar::new();
+        let mut s = rule("S", &[("NP", "SUBJ(*)"), ("VP", "*")]);
+        let mut vp = rule("VP", &[("AUX", "*"), ("V", "*"), ("NP?", "OBJ(*)")]);
+        if discontinuous {
+            s.set_discontinuous(Vec::new());
+            vp.set_discontinuous(vec![(1, 2)]);
+        }
+        g.add_rule(s);
+        g.add_rule(vp);
+        g.add_rule(rule("NP", &[("N", "*")]));
+        g
+    }
+
+    #[test]
+    fn discontinuous_rules_also_combine_adjacent_daughters() {
+        let describe_analyses = |g: &Grammar, words: &[(&str, &str)]| {
+            let result = g.parse_sentence(&readings(words), "S");
+            let trees = result
+                .analyses()
+                .into_iter()
+                .map(|a| a.edge)
+                .collect::<Vec<_>>();
+            describe_all(&trees)
+        };
+        let adjacent = [("N", "dog"), ("AUX", "will"), ("V", "eat"), ("N", "bone")];
+        let analyses = describe_analyses(&auxiliary_grammar(false), &adjacent);
+        assert_eq!(analyses.len(), 1);
+        assert_eq!(
+            describe_analyses(&auxiliary_grammar(true), &adjacent),
+            analyses
+        );
+        let detached = [("AUX", "will"), ("N", "dog"), ("V", "eat"), ("N", "bone")];
+        assert!(describe_analyses(&auxiliary_grammar(false), &detached).is_empty());
+        let analyses = describe_analyses(&auxiliary_grammar(true), &detached);
+        assert_eq!(analyses.len(), 1);
+        assert_eq!(
+            analyses[0],
+            "0-4 S#0(NP#2{1-2}(N),VP#1{0-1 2-4}(AUX{0-1},V{2-3},NP#2{3-4}(N))) ".to_owned()
+                + r#"{"EAT":"+","OBJ":{"BONE":"+"},"SUBJ":{"DOG":"+"},"WILL":"+"}"#
+        );
+    }
+
+    #[test]
+    fn fragment_mode_only_applies_without_complete_analyses() {
+        let mut g = grammar();
+        let result1 = g.parse_sentence(&readings(SENTENCE), "S");
+        g.set_fragments(true);
+        let result2 = g.parse_sentence(&readings(SENTENCE), "S");
+        assert!(result1.fragments.is_none() && result2.fragments.is_none());
+        assert_eq!(result1.count(), result2.count());
+
+        // "dog" can't start a verb phrase and "x" has no reading, which leaves a gap.
+        let words = [
+            ("DET", "the"),
+            ("N", "dog"),
+            ("X", "x"),
+            ("V", "barks"),
+            ("P", "in"),
+        ];
+        let mut tokens = readings(&words);
+        tokens[2].clear();
+        g.set_fragments(false);
+        let result1 = g.parse_sentence(&tokens, "S");
+        g.set_fragments(true);
+        let result2 = g.parse_sentence(&tokens, "S");
+        assert_eq!(result1.count() + result2.count(), 0);
+        assert!(result1.fragments.is_none());
+        let fragments = result2.fragments.unwrap();
+        let trees = fragments.edges.iter().map(|e| e.tree()).collect::<Vec<_>>();
+        assert_eq!(trees, ["NP(DET,N)", "VP(V)", "P"]);
+        assert_eq!(
+            fragments.avm.to_json(),
+            r#"{"FRAGMENTS":{"FIRST":{"DOG":"+","SPEC":{"THE":"+"}},"#.to_owned()
+                + r#""REST":{"FIRST":{"BARKS":"+"},"REST":{"FIRST":{"IN":"+"}}}}}"#
+        );
+    }
+
+    #[test]
+    fn fragments_with_inconsistent_theories_are_left_empty() {
+        let g = Grammar::new();
+        let mut x = Edge::new(0, 1, "X", "w0", vec![vec![eq("A(w0)", "@a")]]);
+        x.theories[0].add_rule(RewriteRule::new(term("B(v)"), term("@b")));
+        let mut y = Edge::new(1, 2, "Y", "w1", vec![vec![eq("A(w1)", "@a")]]);
+        y.theories[0].add_rule(RewriteRule::new(term("B(v)"), term("@c")));
+        let avm = g.fragments_avm(&[Rc::new(x), Rc::new(y)]);
+        assert_eq!(
+            avm.to_json(),
+            r#"{"FRAGMENTS":{"FIRST":{"A":"a"},"REST":{"FIRST":{}}}}"#
+        );
+    }
+
+    #[test]
+    fn pruning_keeps_the_best_edges_of_each_span() {
+        let mut g = grammar();
+        let mut chart1 = chart(SENTENCE);
+        let report1 = g.parse(&mut chart1);
+        let mut pruning = Pruning::new();
+        pruning.max_edges_per_span = Some(1);
+        g.set_pruning(Some(pruning));
+        let mut chart2 = chart(SENTENCE);
+        let report2 = g.parse(&mut chart2);
+        assert!(!report1.is_approximate());
+        assert!(report2.is_approximate() && report2.theories == 0);
+        let mut spans = BTreeMap::new();
+        for edge in chart2.all_edges() {
+            *spans.entry((edge.start, edge.end)).or_insert(0) += 1;
+        }
+        assert!(spans.values().all(|n| *n == 1));
+        assert!(chart2.all_edges().len() < chart1.all_edges().len());
+        // Without a scorer, no edge is better than another, so "chase" stays a noun.
+        assert_eq!(report2.evicted, 0);
+        assert_eq!(chart2.edges_between(5, 6)[0].label, "N");
+        assert!(chart2.edges_between(0, 10).is_empty());
+
+        // Edges built by rules are better than their daughters and evict them.
+        struct Levels;
+        impl EdgeScorer for Levels {
+            fn score(&self, edge: &Edge, _theory: &RewritingSystem) -> f64 {
+                edge.level as f64
             }
-            self.parse_level(chart, level + 1, ctx);
+        }
+        let mut pruning = Pruning::new();
+        pruning.max_edges_per_span = Some(1);
+        pruning.scorer = Some(Rc::new(Levels));
+        g.set_pruning(Some(pruning));
+        let mut chart3 = chart(SENTENCE);
+        let report3 = g.parse(&mut chart3);
+        assert!(report3.evicted > 0);
+        let span = chart3.edges_between(5, 6);
+        assert_eq!(span.len(), 1);
+        assert_eq!(span[0].label, "NP");
+        assert_eq!(span[0].level, 1);
+    }
+
+    #[test]
+    fn n_best_analyses_are_extracted_from_the_forest() {
+        let mut g = grammar();
+        for (index, weight) in [(2, -1.0), (3, -0.5), (5, -0.25)] {
+            g.rules[index].set_weight(weight);
+        }
+        let tokens = readings(SENTENCE);
+        for packing in [false, true] {
+            g.set_packing(packing);
+            let result = g.parse_sentence(&tokens, "S");
+            let mut scores = result
+                .analyses()
+                .iter()
+                .map(|a| a.score)
+                .collect::<Vec<_>>();
+            scores.sort_by(|s1, s2| s2.total_cmp(s1));
+            assert_eq!(scores, [-0.25, -0.5, -1.0]);
+            let n_best = result.n_best(2);
+            assert_eq!(
+                n_best.iter().map(|a| a.score).collect::<Vec<_>>(),
+                scores[..2]
+            );
+            let best = result.best().unwrap();
+            assert_eq!(best.score, -0.25);
+            assert_eq!(
+                best.edge.tree_with_rules(),
+                n_best[0].edge.tree_with_rules()
+            );
+        }
+    }
+
+    #[test]
+    fn lexical_alternatives_packed_before_parsing_are_kept() {
+        let mut g = Grammar::new();
+        g.add_rule(rule("Z", &[("X", "*"), ("Y", "OBJ(*)")]));
+        let mut chart = chart(&[("X", "x"), ("Y", "y")]);
+        chart.set_packing(true);
+        for (i, weight) in [-2.0, -0.5].iter().enumerate() {
+            let mut reading = Reading::new("Y", vec![vec![eq("Y(*)", "@+")]]);
+            reading.weight = *weight;
+            let edge = reading.to_edge(1, 2, &format!("y{}", i));
+            assert_eq!(chart.add_edge(Rc::new(edge)), Ok(false));
+        }
+        g.parse(&mut chart);
+        let z = chart.edges_between(0, 2);
+        assert_eq!(z.len(), 1);
+        assert_eq!(z[0].tree_count(), 3);
+        assert_eq!(z[0].best_score(), Some(0.0));
+        assert_eq!(z[0].n_best(2)[1].children[1].logvar, "y1");
+    }
+
+    #[test]
+    fn edges_are_debugged_with_their_rules() {
+        let mut g = Grammar::new();
+        g.add_rule(rule("Z", &[("X", "*")]));
+        g.add_rule(rule("Z", &[("X", "*"), ("Y?", "OBJ(*)")]));
+        let mut chart = chart(&[("X", "x")]);
+        g.parse(&mut chart);
+        let mut edges = chart
+            .all_edges()
+            .iter()
+            .map(|e| format!("{:?}", e))
+            .collect::<Vec<_>>();
+        edges.sort();
+        assert_eq!(
+            edges,
+            [
+                r#"-0- X -1- / X / {"X": +}"#,
+                r#"-0- Z -1- / Z#0(X) / {"X": +}"#,
+                r#"-0- Z -1- / Z#1(X) / {"X": +}"#,
+            ]
+        );
+    }
+
+    // "Time flies like an arrow", with dead ends such as an imperative verb phrase and a sentence
+    // spanning "flies like an arrow".
+    fn time_flies() -> (Grammar, Chart) {
+        let mut g = Grammar::new();
+        g.add_rule(rule("S", &[("NP", "SUBJ(*)"), ("VP", "*")]));
+        g.add_rule(rule("VP", &[("V", "*"), ("NP?", "OBJ(*)")]));
+        g.add_rule(rule("VP", &[("VP", "*"), ("PP", "ADJ(*)")]));
+        g.add_rule(rule(
+            "NP",
+            &[("DET?", "SPEC(*)"), ("A*", "ADJ(*)"), ("N+", "*")],
+        ));
+        g.add_rule(rule("NP", &[("NP", "*"), ("PP", "ADJ(*)")]));
+        g.add_rule(rule("PP", &[("P", "*"), ("NP", "OBJ(*)")]));
+        let words = [
+            ("N|V|A", "time"),
+            ("N|V", "flies"),
+            ("V|P", "like"),
+            ("DET", "an"),
+            ("N", "arrow"),
+        ];
+        (g, chart(&words))
+    }
+
+    fn spans(edges: &[Rc<Edge>]) -> Vec<String> {
+        let mut spans = edges
+            .iter()
+            .map(|e| format!("{}-{} {}", e.start, e.end, e.tree()))
+            .collect::<Vec<_>>();
+        spans.sort();
+        spans
+    }
+
+    #[test]
+    fn maximal_edges_are_the_complete_analyses() {
+        let (g, chart) = time_flies();
+        let result = g.parse_chart(chart, 0, 5, "S");
+        let maximal = result.chart.maximal_edges("S", 0, 5);
+        assert!(!maximal.is_empty());
+        assert_eq!(spans(&maximal), spans(&result.edges));
+        let all = spans(&result.chart.all_edges());
+        let maximal = spans(&maximal);
+        for dead_end in [
+            "0-5 VP(V,NP(NP(N),PP(P,NP(DET,N))))",
+            "0-5 VP(VP(V,NP(N)),PP(P,NP(DET,N)))",
+            "1-5 S(NP(N),VP(V,NP(DET,N)))",
+            "1-2 NP(N)",
+        ] {
+            assert!(all.contains(&dead_end.to_owned()));
+            assert!(!maximal.contains(&dead_end.to_owned()));
+        }
+    }
+
+    #[test]
+    fn maximal_edges_without_complete_analyses_lie_below_no_other_edge() {
+        let (g, mut chart) = time_flies();
+        g.parse(&mut chart);
+        let maximal = spans(&chart.maximal_edges("S", 0, 4));
+        assert!(maximal.contains(&"1-5 S(NP(N),VP(V,NP(DET,N)))".to_owned()));
+        assert!(maximal.contains(&"0-5 VP(V,NP(NP(N),PP(P,NP(DET,N))))".to_owned()));
+        assert!(!maximal.contains(&"1-2 NP(N)".to_owned()));
+        assert!(!maximal.contains(&"3-5 NP(DET,N)".to_owned()));
+    }
+
+    #[test]
+    fn parsing_output_is_the_same_in_every_run() {
+        // Each run uses new hash maps, which would iterate in another order.
+        let output = || {
+            let mut g = grammar();
+            g.set_packing(true);
+            let result = g.parse_sentence(&readings(SENTENCE), "S");
+            let mut lines = Vec::new();
+            for edge in result.chart.all_edges() {
+                lines.push(format!("{:?} / {}", edge, edge.logvar));
+                for t in &edge.theories {
+                    lines.push(format!("{:?}", t));
+                    lines.push(Avm::from_theory_with_vars(t, &edge.logvar).to_json());
+                }
+            }
+            for analysis in result.analyses() {
+                lines.push(format!("{:?}", analysis.edge));
+            }
+            lines
+        };
+        let lines = output();
+        assert!(lines.iter().any(|line| line.contains("\"#var\":\"g")));
+        for _ in 0..3 {
+            assert_eq!(output(), lines);
         }
     }
 }
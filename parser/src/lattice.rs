@@ -0,0 +1,236 @@
+use crate::chart::*;
+use crate::grammar::*;
+use crate::rewr::*;
+use std::fmt;
+use std::rc::Rc;
+
+/// An arc of a word lattice: a reading spanning two nodes.
+pub struct LatticeArc {
+    pub from: i32,
+    pub to: i32,
+    pub reading: Reading,
+}
+
+/// A word lattice, i.e. lexical readings spanning arbitrary pairs of nodes.
+/// Alternative segmentations of the input are paths between the first and the last node.
+pub struct Lattice {
+    pub arcs: Vec<LatticeArc>,
+}
+
+/// An error in the text of a lattice.
+#[derive(Debug)]
+pub struct LatticeError {
+    /// The line of the error (starting from 1).
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for LatticeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl Lattice {
+    pub fn new() -> Self {
+        Self { arcs: Vec::new() }
+    }
+    /// Adds an arc from one node to another (nodes must not be negative).
+    pub fn add_arc(&mut self, from: i32, to: i32, reading: Reading) {
+        self.arcs.push(LatticeArc {
+            from: from,
+            to: to,
+            reading: reading,
+        });
+    }
+    /// Reads a lattice from text with one arc per line: `FROM TO LABEL CONSTRAINTS`.
+    /// Constraints are equations such as `NUM(SUBJ(*)) = @sg` separated by commas,
+    /// alternative sets of them being separated by `|` (an empty alternative is an error, an arc without constraints isn't).
+    /// Empty lines and lines starting with `#` are ignored.
+    pub fn from_text(text: &str) -> Result<Self, LatticeError> {
+        let mut lattice = Self::new();
+        for (i, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let error = |message: &str| LatticeError {
+                line: i + 1,
+                message: message.to_owned(),
+            };
+            let mut fields = line.split_whitespace();
+            let from = fields.next().and_then(|s| s.parse::<i32>().ok());
+            let to = fields.next().and_then(|s| s.parse::<i32>().ok());
+            let (from, to) = match (from, to) {
+                (Some(from), Some(to)) if from >= 0 && to > from => (from, to),
+                _ => return Err(error("expected two ascending node numbers")),
+            };
+            let label = fields.next().ok_or_else(|| error("missing label"))?;
+            // The rest of the line holds the constraints, in which whitespace doesn't matter.
+            let rest = fields.collect::<Vec<_>>().join(" ");
+            let mut constraints = Vec::new();
+            for alternative in rest.split('|') {
+                if alternative.trim().is_empty() && !rest.is_empty() {
+                    return Err(error("empty alternative"));
+                }
+                let mut conjuncts = Vec::new();
+                for equation in alternative
+                    .split(',')
+                    .map(|s| s.trim())
+                    .filter(|s| !s.is_empty())
+                {
+                    let (t1, t2) = equation
+                        .split_once('=')
+                        .ok_or_else(|| error(&format!("expected an equation: {}", equation)))?;
+                    let t1 = parse_term(t1)
+                        .ok_or_else(|| error(&format!("invalid term: {}", t1.trim())))?;
+                    let t2 = parse_term(t2)
+                        .ok_or_else(|| error(&format!("invalid term: {}", t2.trim())))?;
+                    conjuncts.push(Constraint::Equal(t1, t2));
+                }
+                constraints.push(conjuncts);
+            }
+            lattice.add_arc(from, to, Reading::new(label, constraints));
+        }
+        Ok(lattice)
+    }
+    /// Returns the first node of the lattice.
+    pub fn start(&self) -> i32 {
+        self.arcs.iter().map(|arc| arc.from).min().unwrap_or(0)
+    }
+    /// Returns the last node of the lattice.
+    pub fn end(&self) -> i32 {
+        self.arcs.iter().map(|arc| arc.to).max().unwrap_or(0)
+    }
+    /// Returns a chart with an edge for each arc, the logical variable of the `i`-th arc being `a{i}`.
+    /// Fails if a node is negative.
+    pub fn to_chart(&self) -> Result<Chart, ChartError> {
+        let mut chart = Chart::new();
+        for (i, arc) in self.arcs.iter().enumerate() {
+            let edge = arc.reading.to_edge(arc.from, arc.to, &format!("a{}", i));
+            chart.add_edge(Rc::new(edge))?;
+        }
+        Ok(chart)
+    }
+}
+
+impl Default for Lattice {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Parses a term such as `NUM(SUBJ(*))`.
+fn parse_term(s: &str) -> Option<Term> {
+    let s = s.trim();
+    let mut symbols = Vec::new();
+    let mut rest = s;
+    while let Some((head, arg)) = rest.split_once('(') {
+        symbols.push(head.trim());
+        rest = arg;
+    }
+    let closing = rest.len()
+        - rest
+            .trim_end_matches(|c: char| c == ')' || c.is_whitespace())
+            .len();
+    let rest = &rest[..rest.len() - closing];
+    symbols.push(rest.trim());
+    if rest.contains(')') || s.matches(')').count() != symbols.len() - 1 {
+        return None;
+    }
+    if symbols
+        .iter()
+        .any(|s| s.is_empty() || s.contains(|c: char| c.is_whitespace() || c == ','))
+    {
+        return None;
+    }
+    Some(Term::new(symbols[0], &symbols[1..]))
+}
+
+impl Grammar {
+    /// Parses a word lattice, returning the trees spanning it from its first to its last node.
+    /// Fails if a node is negative.
+    pub fn parse_lattice(
+        &self,
+        lattice: &Lattice,
+        start_symbol: &str,
+    ) -> Result<ParseResult, ChartError> {
+        let mut chart = lattice.to_chart()?;
+        chart.set_packing(self.packing());
+        Ok(self.parse_chart(chart, lattice.start(), lattice.end(), start_symbol))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fields_may_be_separated_by_several_spaces() {
+        let lattice =
+            Lattice::from_text("0  1 A PRED(*)=@a\n1\t3   B  X(*) = @x,  Y(*)=@y | Z(*)=@z\n")
+                .unwrap();
+        assert_eq!(lattice.arcs.len(), 2);
+        let arc = &lattice.arcs[0];
+        assert_eq!((arc.from, arc.to, arc.reading.label.as_str()), (0, 1, "A"));
+        assert_eq!(format!("{:?}", arc.reading.constraints), "[[PRED(*) = @a]]");
+        let arc = &lattice.arcs[1];
+        assert_eq!((arc.from, arc.to, arc.reading.label.as_str()), (1, 3, "B"));
+        assert_eq!(
+            format!("{:?}", arc.reading.constraints),
+            "[[X(*) = @x, Y(*) = @y], [Z(*) = @z]]"
+        );
+        assert_eq!(
+            Lattice::from_text("0  1").err().unwrap().message,
+            "missing label"
+        );
+    }
+
+    #[test]
+    fn empty_alternatives_are_rejected() {
+        let error = Lattice::from_text("0 1 A\n1 2 B X(*)=@x |\n")
+            .err()
+            .unwrap();
+        assert_eq!(
+            (error.line, error.message.as_str()),
+            (2, "empty alternative")
+        );
+        assert!(Lattice::from_text("0 1 A X(*)=@x || Y(*)=@y").is_err());
+        let lattice = Lattice::from_text("0 1 A").unwrap();
+        assert_eq!(format!("{:?}", lattice.arcs[0].reading.constraints), "[[]]");
+    }
+
+    #[test]
+    fn competing_segmentations_are_parsed_in_one_chart() {
+        let mut g = Grammar::new();
+        let item = |path: &str| {
+            let t1 = Term::new(path, &["*"]);
+            vec![vec![Constraint::Equal(t1, Term::new(".", &[]))]]
+        };
+        let head = || {
+            vec![vec![Constraint::Equal(
+                Term::new("*", &[]),
+                Term::new(".", &[]),
+            )]]
+        };
+        g.add_rule(Rule::new("NP", &["N"], vec![head()]));
+        g.add_rule(Rule::new("NP", &["N", "SUF"], vec![head(), item("INFL")]));
+        let lattice =
+            Lattice::from_text("0 2 N PRED(*)=@houses\n0 1 N PRED(*)=@house\n1 2 SUF NUM(*)=@pl\n")
+                .unwrap();
+        let result = g.parse_lattice(&lattice, "NP").unwrap();
+        let mut analyses = result
+            .analyses()
+            .iter()
+            .map(|a| format!("{} {}", a.edge.tree(), a.avms[0].to_json()))
+            .collect::<Vec<_>>();
+        analyses.sort();
+        assert_eq!(
+            analyses,
+            [
+                r#"NP(N) {"PRED":"houses"}"#,
+                r#"NP(N,SUF) {"INFL":{"NUM":"pl"},"PRED":"house"}"#,
+            ]
+        );
+    }
+}
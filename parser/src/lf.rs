@@ -0,0 +1,172 @@
+use crate::avm::*;
+use crate::chart::*;
+use std::collections::HashMap;
+use std::fmt;
+
+/// A mapping of f-structures to literals of a logical form.
+pub struct LfMapping {
+    /// The attribute whose value is the predicate.
+    pub pred: String,
+    /// The grammatical functions whose f-structures are the arguments of the predicate (in order).
+    pub args: Vec<String>,
+}
+
+impl LfMapping {
+    pub fn new(pred: &str, args: &[&str]) -> Self {
+        Self {
+            pred: pred.to_owned(),
+            args: args.iter().map(|&arg| arg.to_owned()).collect(),
+        }
+    }
+}
+
+impl Default for LfMapping {
+    fn default() -> Self {
+        Self::new("PRED", &["SUBJ", "OBJ", "OBJ2"])
+    }
+}
+
+/// A literal of a logical form.
+pub struct Literal {
+    pub predicate: String,
+    pub args: Vec<String>,
+}
+
+impl fmt::Display for Literal {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}'({})", self.predicate, self.args.join(","))
+    }
+}
+
+/// A flat conjunctive logical form in the style of Hobbs' interpretation as abduction.
+/// Each f-structure with a predicate `p` yields a literal `p'(e, x1, ..., xn)` where `e` is the eventuality
+/// of the f-structure and `x1`, ..., `xn` are the entities of its arguments.
+/// If no argument is present, the entity of the f-structure itself is used (as in `dog'(e1,x1)`).
+pub struct LogicalForm {
+    pub literals: Vec<Literal>,
+}
+
+impl LogicalForm {
+    /// Returns the logical forms of an edge, one for each of its theories.
+    pub fn from_edge(edge: &Edge, mapping: &LfMapping) -> Vec<Self> {
+        edge.theories
+            .iter()
+            .map(|rs| Self::from_avm(&Avm::from_theory(rs, &edge.logvar), mapping))
+            .collect()
+    }
+    /// Returns the logical form of an AVM.
+    /// F-structures are numbered in post-order so that arguments precede their predicates.
+    pub fn from_avm(avm: &Avm, mapping: &LfMapping) -> Self {
+        let mut numbering = Numbering {
+            indices: HashMap::new(),
+            tags: HashMap::new(),
+        };
+        numbering.number(avm);
+        let mut lf = Self {
+            literals: Vec::new(),
+        };
+        lf.add_literals(avm, mapping, &numbering);
+        lf
+    }
+    fn add_literals(&mut self, avm: &Avm, mapping: &LfMapping, numbering: &Numbering) {
+        for (_, value) in avm.features() {
+            if let AvmView::Avm(value) = value {
+                self.add_literals(value, mapping, numbering);
+            }
+        }
+        if let Some(AvmView::Atom(pred)) = avm.get(&[&mapping.pred]) {
+            let index = numbering.index(avm);
+            let mut args = vec![format!("e{}", index)];
+            for arg in &mapping.args {
+                let arg_index = match avm.features().find(|(attr, _)| attr == arg) {
+                    Some((_, AvmView::Avm(value))) => Some(numbering.index(value)),
+                    Some((_, AvmView::Tag(tag))) => numbering.tags.get(&tag).cloned(),
+                    _ => None,
+                };
+                if let Some(arg_index) = arg_index {
+                    args.push(format!("x{}", arg_index));
+                }
+            }
+            if args.len() == 1 {
+                args.push(format!("x{}", index));
+            }
+            self.literals.push(Literal {
+                predicate: pred.to_owned(),
+                args: args,
+            });
+        }
+    }
+}
+
+impl fmt::Display for LogicalForm {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}",
+            self.literals
+                .iter()
+                .map(|literal| literal.to_string())
+                .collect::<Vec<_>>()
+                .join(" ∧ ")
+        )
+    }
+}
+
+// A post-order numbering of the f-structures of an AVM.
+struct Numbering {
+    indices: HashMap<*const Avm, usize>,
+    tags: HashMap<usize, usize>,
+}
+
+impl Numbering {
+    fn number(&mut self, avm: &Avm) {
+        for (_, value) in avm.features() {
+            if let AvmView::Avm(value) = value {
+                self.number(value);
+            }
+        }
+        let index = self.indices.len() + 1;
+        self.indices.insert(avm, index);
+        if let Some(tag) = avm.tag() {
+            self.tags.insert(tag, index);
+        }
+    }
+    fn index(&self, avm: &Avm) -> usize {
+        self.indices[&(avm as *const Avm)]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn arguments_are_the_entities_of_grammatical_functions() {
+        let mut avm = Avm::new();
+        avm.set_atom(&["PRED"], "bark");
+        avm.set_atom(&["SUBJ", "PRED"], "dog");
+        let lf = LogicalForm::from_avm(&avm, &LfMapping::default());
+        assert_eq!(lf.to_string(), "dog'(e1,x1) ∧ bark'(e2,x1)");
+    }
+
+    #[test]
+    fn shared_arguments_are_the_same_entity() {
+        // "The dog wants to bark", the subject being shared by both predicates.
+        let mut avm = Avm::new();
+        avm.set_atom(&["PRED"], "want");
+        let mut subj = Avm::new();
+        subj.set_tag(Some(1));
+        subj.set_atom(&["PRED"], "dog");
+        avm.set_avm(&["SUBJ"], subj);
+        avm.set_atom(&["XCOMP", "PRED"], "bark");
+        avm.set_ref(&["XCOMP", "SUBJ"], 1);
+        let lf = LogicalForm::from_avm(&avm, &LfMapping::new("PRED", &["SUBJ", "XCOMP"]));
+        assert_eq!(
+            lf.to_string(),
+            "dog'(e1,x1) ∧ bark'(e2,x1) ∧ want'(e3,x1,x2)"
+        );
+        let literal = &lf.literals[2];
+        assert_eq!(literal.predicate, "want");
+        assert_eq!(literal.args, ["e3", "x1", "x2"]);
+    }
+}
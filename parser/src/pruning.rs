@@ -0,0 +1,104 @@
+use crate::chart::*;
+use crate::rewr::*;
+use std::rc::Rc;
+
+/// A scoring function used to keep the best edges and theories when pruning.
+pub trait EdgeScorer {
+    /// Scores an edge with one of its theories (higher is better).
+    fn score(&self, edge: &Edge, theory: &RewritingSystem) -> f64;
+}
+
+/// Limits on the edges and theories kept during parsing.
+/// The best edges and theories are kept (or the ones found first without a scorer).
+pub struct Pruning {
+    /// The maximum number of edges spanning the same positions.
+    /// An edge of a full span evicts the worst edge of the span if it is better and is dropped otherwise.
+    /// Edges already built from an evicted edge are kept.
+    /// With a scorer, the agenda yields the best of the edges ending at a position first.
+    pub max_edges_per_span: Option<usize>,
+    /// The maximum number of theories of an edge created by a rule.
+    pub max_theories: Option<usize>,
+    pub scorer: Option<Rc<dyn EdgeScorer>>,
+}
+
+impl Pruning {
+    pub fn new() -> Self {
+        Self {
+            max_edges_per_span: None,
+            max_theories: None,
+            scorer: None,
+        }
+    }
+    /// Returns the score of an edge, i.e. the best score of its theories (0 without a scorer).
+    pub fn score(&self, edge: &Edge) -> f64 {
+        match &self.scorer {
+            Some(scorer) => edge
+                .theories
+                .iter()
+                .map(|t| scorer.score(edge, t))
+                .fold(f64::NEG_INFINITY, f64::max),
+            None => 0.0,
+        }
+    }
+    /// Removes the worst theories of an edge exceeding the maximum, returning their number.
+    pub fn prune_theories(&self, edge: &mut Edge) -> usize {
+        let max = match self.max_theories {
+            Some(max) if edge.theories.len() > max => max,
+            _ => return 0,
+        };
+        let pruned = edge.theories.len() - max;
+        if let Some(scorer) = &self.scorer {
+            let scores = edge
+                .theories
+                .iter()
+                .map(|t| scorer.score(edge, t))
+                .collect::<Vec<_>>();
+            let mut scored = scores
+                .into_iter()
+                .zip(edge.theories.drain(..))
+                .collect::<Vec<_>>();
+            scored.sort_by(|(s1, _), (s2, _)| s2.total_cmp(s1));
+            edge.theories = scored.into_iter().map(|(_, t)| t).collect();
+        }
+        edge.theories.truncate(max);
+        pruned
+    }
+}
+
+impl Default for Pruning {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// The numbers of edges and theories pruned during parsing.
+/// Parsing results are approximate unless both are zero.
+#[derive(Clone, Copy, Debug)]
+pub struct PruningReport {
+    /// The number of edges dropped because their span already had the maximum number of edges, none of them worse.
+    pub edges_per_span: usize,
+    /// The number of edges evicted from a full span by a better edge.
+    pub evicted: usize,
+    /// The number of theories dropped from edges with too many theories.
+    pub theories: usize,
+}
+
+impl PruningReport {
+    pub fn new() -> Self {
+        Self {
+            edges_per_span: 0,
+            evicted: 0,
+            theories: 0,
+        }
+    }
+    /// Checks whether anything has been pruned.
+    pub fn is_approximate(&self) -> bool {
+        self.edges_per_span > 0 || self.evicted > 0 || self.theories > 0
+    }
+}
+
+impl Default for PruningReport {
+    fn default() -> Self {
+        Self::new()
+    }
+}
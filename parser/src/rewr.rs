@@ -1,5 +1,5 @@
 use std::cmp::Ordering;
-use std::collections::HashSet;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
 use std::fmt;
 use std::rc::Rc;
 
@@ -38,6 +38,10 @@ impl Term {
             Some(arg) => arg.last(),
         }
     }
+    /// Checks whether the term is an atom.
+    pub fn is_atom(&self) -> bool {
+        self.arg.is_none() && self.head.starts_with('@')
+    }
     /// Converts the term into a vector.
     pub fn as_vec(&self) -> Vec<String> {
         let mut v = Vec::new();
@@ -51,22 +55,106 @@ impl Term {
     }
 }
 
-impl PartialOrd for Term {
-    fn partial_cmp(&self, other: &Term) -> Option<Ordering> {
+impl Ord for Term {
+    fn cmp(&self, other: &Term) -> Ordering {
         let c = self.size().cmp(&other.size());
         if c != Ordering::Equal {
-            return Some(c);
+            return c;
         }
         let c = self.head.cmp(&other.head);
         if c != Ordering::Equal {
-            return Some(c);
+            return c;
         }
         if let Some(arg1) = &self.arg {
             if let Some(arg2) = &other.arg {
-                return arg1.partial_cmp(arg2);
+                return arg1.cmp(arg2);
             }
         }
-        Some(Ordering::Equal)
+        Ordering::Equal
+    }
+}
+
+impl PartialOrd for Term {
+    fn partial_cmp(&self, other: &Term) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+/// A reduction ordering used to orient equations into rewrite rules.
+/// It must be well-founded and compatible with the term structure, i.e. `t1 > t2` must imply `f(t1) > f(t2)`.
+pub trait TermOrdering {
+    /// Compares two terms.
+    fn compare(&self, t1: &Term, t2: &Term) -> Ordering;
+}
+
+/// The default ordering: by length, then by head symbol, then by argument.
+pub struct LengthLexOrdering;
+
+impl TermOrdering for LengthLexOrdering {
+    fn compare(&self, t1: &Term, t2: &Term) -> Ordering {
+        t1.cmp(t2)
+    }
+}
+
+/// An ordering by length in which atoms are smaller than any other symbol and older variables are smaller than younger ones.
+/// Variables are symbols consisting of one of the given prefixes and a number; the kinds of variables are ordered
+/// by the position of their prefix in the list and variables of the same kind by their number (so `g2` is older than `g10`)
+/// and then by spelling (so that `g02` and `g2` are distinct).
+/// Other symbols are greater than variables and ordered by prefix, number and spelling.
+/// Normal forms are thus invariant under renamings of variables that preserve their age, which isn't the case
+/// for `LengthLexOrdering` (where `g10` is smaller than `g2`).
+pub struct AgeOrdering {
+    prefixes: Vec<String>,
+}
+
+impl AgeOrdering {
+    /// Creates an ordering with the given prefixes of variables, from the oldest kind to the youngest one.
+    pub fn new(prefixes: &[&str]) -> Self {
+        Self {
+            prefixes: prefixes.iter().map(|p| p.to_string()).collect(),
+        }
+    }
+    fn compare_symbols(&self, s1: &str, s2: &str) -> Ordering {
+        match (s1.starts_with('@'), s2.starts_with('@')) {
+            (true, false) => return Ordering::Less,
+            (false, true) => return Ordering::Greater,
+            (true, true) => return s1.cmp(s2),
+            _ => {}
+        }
+        let (p1, n1) = Self::split_index(s1);
+        let (p2, n2) = Self::split_index(s2);
+        let kind1 = n1.and_then(|_| self.prefixes.iter().position(|p| p == p1));
+        let kind2 = n2.and_then(|_| self.prefixes.iter().position(|p| p == p2));
+        match (kind1, kind2) {
+            (Some(k1), Some(k2)) => k1.cmp(&k2).then(n1.cmp(&n2)).then(s1.cmp(s2)),
+            (Some(_), None) => Ordering::Less,
+            (None, Some(_)) => Ordering::Greater,
+            (None, None) => p1.cmp(p2).then(n1.cmp(&n2)).then(s1.cmp(s2)),
+        }
+    }
+    /// Splits a symbol into its prefix and numeric suffix.
+    fn split_index(s: &str) -> (&str, Option<u64>) {
+        let prefix = s.trim_end_matches(|c: char| c.is_ascii_digit());
+        (prefix, s[prefix.len()..].parse().ok())
+    }
+}
+
+impl Default for AgeOrdering {
+    /// Returns the ordering of the variables of lexical edges (`w`), lattice arcs (`a`) and rule applications (`g`).
+    fn default() -> Self {
+        Self::new(&["w", "a", "g"])
+    }
+}
+
+impl TermOrdering for AgeOrdering {
+    fn compare(&self, t1: &Term, t2: &Term) -> Ordering {
+        t1.size()
+            .cmp(&t2.size())
+            .then_with(|| self.compare_symbols(&t1.head, &t2.head))
+            .then_with(|| match (&t1.arg, &t2.arg) {
+                (Some(arg1), Some(arg2)) => self.compare(arg1, arg2),
+                _ => Ordering::Equal,
+            })
     }
 }
 
@@ -80,7 +168,7 @@ impl fmt::Debug for Term {
 }
 
 /// A rewrite rule.
-#[derive(Eq, PartialEq, Hash, Clone)]
+#[derive(Eq, PartialEq, Ord, PartialOrd, Hash, Clone)]
 pub struct RewriteRule {
     pub lhs: Term,
     pub rhs: Term,
@@ -88,7 +176,11 @@ pub struct RewriteRule {
 
 impl RewriteRule {
     pub fn new(t1: Term, t2: Term) -> Self {
-        if t1 > t2 {
+        Self::new_with_ordering(t1, t2, &LengthLexOrdering)
+    }
+    /// Creates a rule oriented by the given ordering.
+    pub fn new_with_ordering(t1: Term, t2: Term, ordering: &dyn TermOrdering) -> Self {
+        if ordering.compare(&t1, &t2) == Ordering::Greater {
             RewriteRule { lhs: t1, rhs: t2 }
         } else {
             RewriteRule { lhs: t2, rhs: t1 }
@@ -119,19 +211,41 @@ impl fmt::Debug for RewriteRule {
     }
 }
 
+/// How a rewriting system treats equations that make the represented feature structure cyclic.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum CyclePolicy {
+    /// Cycles are allowed (they are marked when the structure is extracted).
+    Allow,
+    /// Equations creating a cycle are rejected like inconsistent ones.
+    Reject,
+}
+
 /// A rewriting system.
 /// It uses the Knuth-Bendix completion procedure (which is decidable in this special case).
+/// Rules are kept ordered so that iterating over them is deterministic.
 #[derive(Clone)]
 pub struct RewritingSystem {
-    pub rules: HashSet<RewriteRule>,
+    pub rules: BTreeSet<RewriteRule>,
+    ordering: Rc<dyn TermOrdering>,
+    cycle_policy: CyclePolicy,
 }
 
 impl RewritingSystem {
     pub fn new() -> Self {
+        Self::with_ordering(Rc::new(LengthLexOrdering))
+    }
+    /// Creates a rewriting system orienting its rules by the given ordering.
+    pub fn with_ordering(ordering: Rc<dyn TermOrdering>) -> Self {
         Self {
-            rules: HashSet::new(),
+            rules: BTreeSet::new(),
+            ordering: ordering,
+            cycle_policy: CyclePolicy::Allow,
         }
     }
+    /// Sets how equations creating cycles are treated.
+    pub fn set_cycle_policy(&mut self, policy: CyclePolicy) {
+        self.cycle_policy = policy;
+    }
     /// Returns a norm of the term.
     pub fn norm(&self, t: &Term) -> Term {
         for rule in &self.rules {
@@ -157,7 +271,7 @@ impl RewritingSystem {
                 }
             }
         }
-        let new_rule = RewriteRule::new(t1, t2);
+        let new_rule = RewriteRule::new_with_ordering(t1, t2, self.ordering.as_ref());
         if self.rules.contains(&new_rule) {
             return true;
         }
@@ -167,11 +281,21 @@ impl RewritingSystem {
                 let t1 = self.norm(&t);
                 let t2 = self.norm(&rule.rhs);
                 if t1 != t2 {
-                    new_rules.push(RewriteRule::new(t1.clone(), t2.clone()));
+                    new_rules.push(RewriteRule::new_with_ordering(
+                        t1.clone(),
+                        t2.clone(),
+                        self.ordering.as_ref(),
+                    ));
                 }
             }
         }
+        let node = new_rule.rhs.clone();
         self.rules.insert(new_rule);
+        // The new rule merges the node of its left-hand side into the one of its right-hand side,
+        // so any cycle it creates goes through the latter.
+        if self.cycle_policy == CyclePolicy::Reject && self.is_on_cycle(&node) {
+            return false;
+        }
         for rule in new_rules {
             if !self.add_rule(rule) {
                 return false;
@@ -179,6 +303,92 @@ impl RewritingSystem {
         }
         true
     }
+    /// Returns the graph of the feature structure represented by the rewriting system.
+    /// Its nodes are normal forms, each mapping attributes to the normal forms of their values.
+    pub fn feature_graph(&self) -> BTreeMap<Term, BTreeMap<String, Term>> {
+        let mut graph = BTreeMap::new();
+        for rule in &self.rules {
+            for t in [&rule.lhs, &rule.rhs] {
+                let mut t = t;
+                while let Some(arg) = &t.arg {
+                    graph
+                        .entry(self.norm(arg))
+                        .or_insert_with(BTreeMap::new)
+                        .insert(t.head.as_ref().to_owned(), self.norm(t));
+                    t = arg.as_ref();
+                }
+            }
+        }
+        graph
+    }
+    /// Checks whether the represented feature structure contains a cycle.
+    pub fn is_cyclic(&self) -> bool {
+        let graph = self.feature_graph();
+        let mut done = HashSet::new();
+        for node in graph.keys() {
+            if Self::find_cycle(&graph, node, &mut Vec::new(), &mut done) {
+                return true;
+            }
+        }
+        false
+    }
+    /// Checks whether a node of the represented feature structure lies on a cycle.
+    /// Only the terms occurring in the rules are followed: the value of an attribute not occurring
+    /// in them is irreducible and so are the values below it, none of which is the given node.
+    fn is_on_cycle(&self, node: &Term) -> bool {
+        let mut features = HashMap::new();
+        for rule in &self.rules {
+            for t in [&rule.lhs, &rule.rhs] {
+                let mut t = t;
+                while let Some(arg) = &t.arg {
+                    features
+                        .entry(arg.as_ref())
+                        .or_insert_with(Vec::new)
+                        .push(t);
+                    t = arg.as_ref();
+                }
+            }
+        }
+        let node = self.norm(node);
+        let mut stack = vec![node.clone()];
+        let mut done = HashSet::new();
+        while let Some(t) = stack.pop() {
+            for feature in features.get(&t).into_iter().flatten() {
+                let value = self.norm(feature);
+                if value == node {
+                    return true;
+                }
+                if done.insert(value.clone()) {
+                    stack.push(value);
+                }
+            }
+        }
+        false
+    }
+    fn find_cycle(
+        graph: &BTreeMap<Term, BTreeMap<String, Term>>,
+        node: &Term,
+        path: &mut Vec<Term>,
+        done: &mut HashSet<Term>,
+    ) -> bool {
+        if path.contains(node) {
+            return true;
+        }
+        if done.contains(node) {
+            return false;
+        }
+        if let Some(features) = graph.get(node) {
+            path.push(node.clone());
+            for value in features.values() {
+                if Self::find_cycle(graph, value, path, done) {
+                    return true;
+                }
+            }
+            path.pop();
+        }
+        done.insert(node.clone());
+        false
+    }
 }
 
 impl fmt::Debug for RewritingSystem {
@@ -190,3 +400,139 @@ impl fmt::Debug for RewritingSystem {
         write!(f, "{}", s)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn term(s: &str) -> Term {
+        let symbols = s
+            .split('(')
+            .map(|s| s.trim_end_matches(')'))
+            .collect::<Vec<_>>();
+        Term::new(symbols[0], &symbols[1..])
+    }
+    fn system(ordering: Rc<dyn TermOrdering>, equations: &[(&str, &str)]) -> RewritingSystem {
+        let mut rs = RewritingSystem::with_ordering(ordering);
+        for (t1, t2) in equations {
+            assert!(rs.add_rule(RewriteRule::new(term(t1), term(t2))));
+        }
+        rs
+    }
+    fn rename(t: &Term, renaming: &[(&str, &str)]) -> Term {
+        let head = renaming
+            .iter()
+            .find(|(from, _)| *from == t.head.as_ref())
+            .map_or(t.head.as_ref(), |(_, to)| to);
+        Term {
+            head: Rc::from(head),
+            arg: t.arg.as_ref().map(|arg| Rc::new(rename(arg, renaming))),
+        }
+    }
+
+    const EQUATIONS: &[(&str, &str)] = &[
+        ("SUBJ(g10)", "g2"),
+        ("OBJ(g10)", "w3"),
+        ("XCOMP(g10)", "g11"),
+        ("SUBJ(g11)", "SUBJ(g10)"),
+        ("NUM(g2)", "@sg"),
+    ];
+    // An age-preserving renaming of the variables of `EQUATIONS`.
+    const RENAMING: &[(&str, &str)] =
+        &[("g2", "g7"), ("g10", "g30"), ("g11", "g100"), ("w3", "w1")];
+
+    #[test]
+    fn age_ordering_norms_are_invariant_under_renaming() {
+        let ordering: Rc<dyn TermOrdering> = Rc::new(AgeOrdering::default());
+        let renamed = EQUATIONS
+            .iter()
+            .map(|(t1, t2)| (rename(&term(t1), RENAMING), rename(&term(t2), RENAMING)))
+            .collect::<Vec<_>>();
+        let rs1 = system(ordering.clone(), EQUATIONS);
+        let mut rs2 = RewritingSystem::with_ordering(ordering);
+        for (t1, t2) in renamed {
+            assert!(rs2.add_rule(RewriteRule::new(t1, t2)));
+        }
+        for t in [
+            "g2",
+            "g10",
+            "g11",
+            "SUBJ(g11)",
+            "SUBJ(XCOMP(g10))",
+            "OBJ(g10)",
+        ] {
+            let t = term(t);
+            assert_eq!(
+                rename(&rs1.norm(&t), RENAMING),
+                rs2.norm(&rename(&t, RENAMING))
+            );
+        }
+    }
+
+    #[test]
+    fn length_lex_norms_depend_on_spelling() {
+        // Renaming `g10` to `g3` preserves the age of the variables but not their spelling order.
+        let ordering: Rc<dyn TermOrdering> = Rc::new(LengthLexOrdering);
+        let rs1 = system(ordering.clone(), &[("g2", "g10")]);
+        let rs2 = system(ordering, &[("g2", "g3")]);
+        assert_eq!(rs1.norm(&term("g2")), term("g10"));
+        assert_eq!(rs2.norm(&term("g3")), term("g2"));
+        let ordering: Rc<dyn TermOrdering> = Rc::new(AgeOrdering::default());
+        let rs1 = system(ordering.clone(), &[("g2", "g10")]);
+        let rs2 = system(ordering, &[("g2", "g3")]);
+        assert_eq!(rs1.norm(&term("g10")), term("g2"));
+        assert_eq!(rs2.norm(&term("g3")), term("g2"));
+    }
+
+    #[test]
+    fn age_ordering_prefers_atoms_and_older_variables() {
+        let rs = system(
+            Rc::new(AgeOrdering::default()),
+            &[
+                ("g10", "g2"),
+                ("g2", "w5"),
+                ("A(g10)", "B(g3)"),
+                ("C(g3)", "@x"),
+            ],
+        );
+        assert_eq!(rs.norm(&term("g10")), term("w5"));
+        assert_eq!(rs.norm(&term("B(g3)")), term("A(w5)"));
+        assert_eq!(rs.norm(&term("C(g3)")), term("@x"));
+    }
+
+    #[test]
+    fn age_ordering_is_total_on_distinct_symbols() {
+        let ordering = AgeOrdering::default();
+        assert_ne!(ordering.compare(&term("g02"), &term("g2")), Ordering::Equal);
+        assert_eq!(
+            RewriteRule::new_with_ordering(term("g02"), term("g2"), &ordering),
+            RewriteRule::new_with_ordering(term("g2"), term("g02"), &ordering)
+        );
+    }
+
+    #[test]
+    fn cyclic_equations_are_rejected_or_allowed() {
+        let mut rs = RewritingSystem::new();
+        rs.set_cycle_policy(CyclePolicy::Reject);
+        assert!(!rs.add_rule(RewriteRule::new(term("X(g1)"), term("g1"))));
+        let mut rs = RewritingSystem::new();
+        assert!(rs.add_rule(RewriteRule::new(term("X(g1)"), term("g1"))));
+        assert!(rs.is_cyclic());
+        assert_eq!(
+            crate::avm::Avm::from_theory(&rs, "g1").to_json(),
+            r##"{"#tag":1,"X":{"#ref":1,"#cycle":true}}"##
+        );
+    }
+
+    #[test]
+    fn cycles_through_other_rules_are_rejected() {
+        let mut rs = RewritingSystem::with_ordering(Rc::new(AgeOrdering::default()));
+        rs.set_cycle_policy(CyclePolicy::Reject);
+        assert!(rs.add_rule(RewriteRule::new(term("G(g1)"), term("g2"))));
+        assert!(rs.add_rule(RewriteRule::new(term("A(g3)"), term("g1"))));
+        assert!(rs.add_rule(RewriteRule::new(term("B(g3)"), term("g1"))));
+        assert!(!rs.is_cyclic());
+        assert!(!rs.add_rule(RewriteRule::new(term("F(g2)"), term("g1"))));
+        assert!(rs.is_cyclic());
+    }
+}
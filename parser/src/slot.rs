@@ -0,0 +1,274 @@
+use crate::chart::*;
+use crate::rewr::*;
+use std::collections::HashMap;
+use std::fmt;
+
+/// A word in a Slot Grammar analysis.
+pub struct SlotWord {
+    /// The position of the word in the analysis (starting from 1).
+    pub index: usize,
+    pub label: String,
+    /// The value of `PRED` if the word contributes it.
+    pub lemma: Option<String>,
+    /// The atomic features of the word's f-structure (except `PRED`) in alphabetical order.
+    pub features: Vec<(String, String)>,
+    /// The index of the head (`None` for the top word).
+    pub head: Option<usize>,
+    /// The slot filled by the word (`None` for the top word and for words sharing the f-structure of their head).
+    pub slot: Option<String>,
+    /// The slots opened by the word and the indices of their fillers.
+    pub frame: Vec<(String, Option<usize>)>,
+}
+
+/// A Slot Grammar-like analysis of a complete parse.
+/// Slots are the attributes with non-atomic values, the f-structures of the words being their fillers.
+pub struct SlotAnalysis {
+    pub words: Vec<SlotWord>,
+    /// The index of the word heading the f-structure of the whole parse (if any).
+    pub top: Option<usize>,
+}
+
+impl SlotAnalysis {
+    /// Returns the analyses of the given edge, one for each of its theories.
+    pub fn from_edge(edge: &Edge) -> Vec<Self> {
+        edge.theories
+            .iter()
+            .map(|rs| Self::from_theory(edge, rs))
+            .collect()
+    }
+    /// Returns the analysis of the given edge for one of its theories.
+    pub fn from_theory(edge: &Edge, rs: &RewritingSystem) -> Self {
+        let leaves = edge.leaves();
+        let graph = rs.feature_graph();
+        let nodes = leaves
+            .iter()
+            .map(|leaf| rs.norm(&Term::new(&leaf.logvar, &[])))
+            .collect::<Vec<_>>();
+        // The head of the words sharing an f-structure is the first one contributing a PRED.
+        let mut heads = HashMap::new();
+        for (i, leaf) in leaves.iter().enumerate() {
+            let contributes_pred = leaf
+                .theories
+                .iter()
+                .any(|t| t.norm(&Term::new("PRED", &[&leaf.logvar])).is_atom());
+            if contributes_pred {
+                heads.entry(nodes[i].clone()).or_insert(i + 1);
+            }
+        }
+        for (i, node) in nodes.iter().enumerate() {
+            heads.entry(node.clone()).or_insert(i + 1);
+        }
+        let mut words = Vec::with_capacity(leaves.len());
+        for (i, leaf) in leaves.iter().enumerate() {
+            let index = i + 1;
+            let node = &nodes[i];
+            let mut features = graph
+                .get(node)
+                .map(|features| {
+                    features
+                        .iter()
+                        .filter(|(_, value)| value.is_atom())
+                        .map(|(attr, value)| (attr.to_owned(), value.head[1..].to_owned()))
+                        .collect::<Vec<_>>()
+                })
+                .unwrap_or_default();
+            features.sort();
+            let is_head = heads[node] == index;
+            let lemma = features
+                .iter()
+                .find(|(attr, _)| attr == "PRED")
+                .filter(|_| is_head)
+                .map(|(_, value)| value.to_owned());
+            features.retain(|(attr, _)| attr != "PRED");
+            let mut word = SlotWord {
+                index: index,
+                label: leaf.label.to_owned(),
+                lemma: lemma,
+                features: features,
+                head: None,
+                slot: None,
+                frame: Vec::new(),
+            };
+            if !is_head {
+                word.head = Some(heads[node]);
+            } else {
+                'nloop: for other in &nodes {
+                    if other == node {
+                        continue;
+                    }
+                    if let Some(features) = graph.get(other) {
+                        for (attr, value) in features {
+                            if value == node {
+                                word.head = Some(heads[other]);
+                                word.slot = Some(attr.to_owned());
+                                break 'nloop;
+                            }
+                        }
+                    }
+                }
+                if let Some(features) = graph.get(node) {
+                    for (attr, value) in features {
+                        if !value.is_atom() {
+                            word.frame
+                                .push((attr.to_owned(), heads.get(value).cloned()));
+                        }
+                    }
+                    word.frame.sort();
+                }
+            }
+            words.push(word);
+        }
+        let top = heads.get(&rs.norm(&Term::new(&edge.logvar, &[]))).cloned();
+        Self {
+            words: words,
+            top: top,
+        }
+    }
+}
+
+impl fmt::Display for SlotAnalysis {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut rows = Vec::with_capacity(self.words.len());
+        for word in &self.words {
+            let slot = match (&word.slot, word.head) {
+                (Some(slot), Some(head)) => format!("{}({})", slot, head),
+                (None, Some(head)) => format!("-({})", head),
+                _ => "top".to_owned(),
+            };
+            let mut args = vec![word.index.to_string()];
+            for (_, filler) in &word.frame {
+                args.push(filler.map_or("u".to_owned(), |i| i.to_string()));
+            }
+            let sense = format!(
+                "{}({})",
+                word.lemma.as_deref().unwrap_or("_"),
+                args.join(",")
+            );
+            let features = word
+                .features
+                .iter()
+                .map(|(attr, value)| format!("{}={}", attr, value))
+                .collect::<Vec<_>>()
+                .join(" ");
+            rows.push([slot, sense, word.label.to_owned(), features]);
+        }
+        let mut widths = [0; 3];
+        for row in &rows {
+            for (i, width) in widths.iter_mut().enumerate() {
+                *width = (*width).max(row[i].chars().count());
+            }
+        }
+        for row in rows {
+            let line = format!(
+                "{:<w0$}  {:<w1$}  {:<w2$}  {}",
+                row[0],
+                row[1],
+                row[2],
+                row[3],
+                w0 = widths[0],
+                w1 = widths[1],
+                w2 = widths[2]
+            );
+            writeln!(f, "{}", line.trim_end())?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::grammar::*;
+    use std::rc::Rc;
+
+    fn term(s: &str) -> Term {
+        let symbols = s
+            .split('(')
+            .map(|s| s.trim_end_matches(')'))
+            .collect::<Vec<_>>();
+        Term::new(symbols[0], &symbols[1..])
+    }
+
+    fn eq(t1: &str, t2: &str) -> Constraint {
+        Constraint::Equal(term(t1), term(t2))
+    }
+
+    // Parses "the dog will chase cats", in which the auxiliary shares the f-structure of the verb.
+    fn parse() -> Rc<Edge> {
+        let mut g = Grammar::new();
+        let item = |path: &str| vec![vec![eq(path, ".")]];
+        g.add_rule(Rule::new(
+            "S",
+            &["NP", "VP"],
+            vec![item("SUBJ(*)"), item("*")],
+        ));
+        g.add_rule(Rule::new(
+            "VP",
+            &["AUX?", "V", "NP?"],
+            vec![item("*"), item("*"), item("OBJ(*)")],
+        ));
+        g.add_rule(Rule::new(
+            "NP",
+            &["DET?", "N"],
+            vec![item("SPEC(*)"), item("*")],
+        ));
+        let tokens = [
+            ("DET", vec![eq("PRED(*)", "@the")]),
+            ("N", vec![eq("PRED(*)", "@dog"), eq("NUM(*)", "@sg")]),
+            ("AUX", vec![eq("TENSE(*)", "@fut")]),
+            ("V", vec![eq("PRED(*)", "@chase")]),
+            ("N", vec![eq("PRED(*)", "@cat"), eq("NUM(*)", "@pl")]),
+        ]
+        .into_iter()
+        .map(|(label, constraints)| vec![Reading::new(label, vec![constraints])])
+        .collect::<Vec<_>>();
+        let result = g.parse_sentence(&tokens, "S");
+        assert_eq!(result.edges.len(), 1);
+        result.edges[0].clone()
+    }
+
+    #[test]
+    fn words_fill_the_slots_of_their_heads() {
+        let analyses = SlotAnalysis::from_edge(&parse());
+        assert_eq!(analyses.len(), 1);
+        let analysis = &analyses[0];
+        let words = analysis
+            .words
+            .iter()
+            .map(|w| {
+                (
+                    w.lemma.as_deref(),
+                    w.head,
+                    w.slot.as_deref(),
+                    w.frame.clone(),
+                )
+            })
+            .collect::<Vec<_>>();
+        let frame = |slots: &[(&str, usize)]| {
+            slots
+                .iter()
+                .map(|(slot, filler)| (slot.to_string(), Some(*filler)))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            words,
+            [
+                (Some("the"), Some(2), Some("SPEC"), frame(&[])),
+                (Some("dog"), Some(4), Some("SUBJ"), frame(&[("SPEC", 1)])),
+                (None, Some(4), None, frame(&[])),
+                (Some("chase"), None, None, frame(&[("OBJ", 5), ("SUBJ", 2)])),
+                (Some("cat"), Some(4), Some("OBJ"), frame(&[])),
+            ]
+        );
+        assert_eq!(analysis.top, Some(4));
+        assert_eq!(
+            analysis.to_string(),
+            "SPEC(2)  the(1)        DET
+SUBJ(4)  dog(2,1)      N    NUM=sg
+-(4)     _(3)          AUX  TENSE=fut
+top      chase(4,5,2)  V    TENSE=fut
+OBJ(4)   cat(5)        N    NUM=pl
+"
+        );
+    }
+}